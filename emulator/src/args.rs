@@ -0,0 +1,73 @@
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+#[derive(Debug, Parser)]
+#[command(author, version)]
+pub struct Args {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run a microcontroller script against the running server
+    Run(RunArgs),
+    /// Run a microcontroller script against a recording
+    Replay(ReplayArgs),
+}
+
+#[derive(Debug, clap::Args)]
+pub struct RunArgs {
+    /// Microcontroller Lua script
+    pub script: PathBuf,
+    /// CSV with `N<channel>`/`B<channel>` columns, one row per tick
+    #[arg(long, conflicts_with = "inputs")]
+    pub csv: Option<PathBuf>,
+    /// Lua script defining `inputs(tick)`; returning false stops the run
+    #[arg(long)]
+    pub inputs: Option<PathBuf>,
+    /// `name=value` property of the microcontroller
+    #[arg(short, long = "property", value_parser = parse_property)]
+    pub properties: Vec<(String, String)>,
+    /// Stop after this many ticks
+    #[arg(short, long)]
+    pub ticks: Option<u64>,
+    /// Run as fast as possible instead of 60 ticks/s
+    #[arg(long)]
+    pub fast: bool,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct ReplayArgs {
+    /// Microcontroller Lua script
+    pub script: PathBuf,
+    /// CSV saved by the viewer or `.json` downloaded from the server
+    pub recording: PathBuf,
+    /// `key=N<channel>` or `key=B<channel>` feeding a recorded key to an input
+    #[arg(short, long = "map", value_parser = parse_property)]
+    pub maps: Vec<(String, String)>,
+    /// `N<channel>=key` naming an output channel
+    #[arg(short, long = "output", value_parser = parse_property)]
+    pub outputs: Vec<(String, String)>,
+    /// Prefix of output channels without a name
+    #[arg(long, default_value = "out")]
+    pub prefix: String,
+    /// `name=value` property of the microcontroller
+    #[arg(long = "property", value_parser = parse_property)]
+    pub properties: Vec<(String, String)>,
+    /// CSV with the recorded and output keys; stdout unless pushing
+    #[arg(long)]
+    pub out: Option<PathBuf>,
+    /// Push the recorded and output keys to the server on this port
+    #[arg(long)]
+    pub push: Option<u16>,
+    /// Write token of the server
+    #[arg(long)]
+    pub token: Option<String>,
+}
+
+fn parse_property(s: &str) -> Result<(String, String), String> {
+    s.split_once('=')
+        .map(|(k, v)| (k.to_owned(), v.to_owned()))
+        .ok_or_else(|| format!("expected name=value: {}", s))
+}
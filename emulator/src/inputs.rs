@@ -0,0 +1,124 @@
+//! Sources of the composite input fed to the microcontroller each tick.
+use crate::machine::Io;
+use mlua::{Function, Lua};
+use std::{
+    cell::RefCell,
+    fs::File,
+    io::{BufRead, BufReader, Lines},
+    path::Path,
+    rc::Rc,
+};
+
+pub enum Column {
+    Number(usize),
+    Bool(usize),
+    Ignored,
+}
+
+pub enum Source {
+    /// Header of `N<channel>` and `B<channel>` columns and a row per tick
+    Csv {
+        lines: Lines<BufReader<File>>,
+        columns: Vec<Column>,
+    },
+    /// Lua script defining `inputs(tick)` which calls `input.setNumber` and `input.setBool`
+    Script(Lua),
+    None,
+}
+
+impl Source {
+    pub fn csv(path: &Path) -> Result<Self, String> {
+        let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut lines = BufReader::new(file).lines();
+        let header = match lines.next() {
+            Some(header) => header.map_err(|e| e.to_string())?,
+            None => return Err(format!("{}: empty file", path.display())),
+        };
+        let columns = header
+            .split(',')
+            .map(|name| {
+                let name = name.trim();
+                let channel = name.get(1..).and_then(|i| i.parse().ok());
+                match (name.chars().next(), channel) {
+                    (Some('N'), Some(i)) => Column::Number(i),
+                    (Some('B'), Some(i)) => Column::Bool(i),
+                    _ => {
+                        log::warn!("ignoring column {:?}", name);
+                        Column::Ignored
+                    }
+                }
+            })
+            .collect();
+        Ok(Self::Csv { lines, columns })
+    }
+
+    pub fn script(path: &Path, io: Rc<RefCell<Io>>) -> Result<Self, String> {
+        let script =
+            std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let lua = Lua::new();
+        let init = || -> mlua::Result<()> {
+            let input = lua.create_table()?;
+            let io_ = io.clone();
+            input.set(
+                "setNumber",
+                lua.create_function(move |_, (i, v): (usize, f32)| {
+                    if let Some(c) = io_.borrow_mut().input.numbers.get_mut(i.wrapping_sub(1)) {
+                        *c = v;
+                    }
+                    Ok(())
+                })?,
+            )?;
+            input.set(
+                "setBool",
+                lua.create_function(move |_, (i, v): (usize, bool)| {
+                    if let Some(c) = io.borrow_mut().input.bools.get_mut(i.wrapping_sub(1)) {
+                        *c = v;
+                    }
+                    Ok(())
+                })?,
+            )?;
+            lua.globals().set("input", input)?;
+            lua.load(&script).set_name("inputs").exec()
+        };
+        init().map_err(|e| e.to_string())?;
+        Ok(Self::Script(lua))
+    }
+
+    /// Sets the input of `tick`, returning false when the source is exhausted.
+    pub fn apply(&mut self, tick: u64, io: &RefCell<Io>) -> Result<bool, String> {
+        match self {
+            Source::Csv { lines, columns } => {
+                let line = match lines.next() {
+                    Some(line) => line.map_err(|e| e.to_string())?,
+                    None => return Ok(false),
+                };
+                let mut io = io.borrow_mut();
+                for (column, value) in columns.iter().zip(line.split(',')) {
+                    let value = value.trim();
+                    match *column {
+                        Column::Number(i) => {
+                            if let Some(c) = io.input.numbers.get_mut(i.wrapping_sub(1)) {
+                                *c = value.parse().unwrap_or_default();
+                            }
+                        }
+                        Column::Bool(i) => {
+                            if let Some(c) = io.input.bools.get_mut(i.wrapping_sub(1)) {
+                                *c = value == "1" || value == "true";
+                            }
+                        }
+                        Column::Ignored => {}
+                    }
+                }
+                Ok(true)
+            }
+            Source::Script(lua) => {
+                let inputs: Function = lua.globals().get("inputs").map_err(|e| e.to_string())?;
+                inputs
+                    .call::<_, Option<bool>>(tick)
+                    .map(|more| more != Some(false))
+                    .map_err(|e| e.to_string())
+            }
+            Source::None => Ok(true),
+        }
+    }
+}
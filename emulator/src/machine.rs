@@ -0,0 +1,254 @@
+//! Lua microcontroller with the Stormworks `input`, `output`, `property`
+//! and `async` APIs.
+use mlua::{Function, Lua};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    io::{Read, Write},
+    net::TcpStream,
+    rc::Rc,
+    sync::mpsc,
+};
+
+/// Channels of a composite signal
+pub const CHANNELS: usize = 32;
+
+#[derive(Debug, Clone)]
+pub struct Composite {
+    pub numbers: [f32; CHANNELS],
+    pub bools: [bool; CHANNELS],
+}
+
+impl Default for Composite {
+    fn default() -> Self {
+        Self {
+            numbers: [0.0; CHANNELS],
+            bools: [false; CHANNELS],
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Io {
+    pub input: Composite,
+    pub output: Composite,
+    /// bit `n` is set once output channel `n + 1` has been written
+    pub written_numbers: u32,
+    pub written_bools: u32,
+}
+
+struct Reply {
+    port: u16,
+    request: String,
+    response: String,
+}
+
+#[derive(Debug, Default)]
+pub struct HttpStats {
+    pub requests: usize,
+    pub replies: usize,
+    /// replies other than `OK` and `MISSING`, including connection errors
+    pub failures: usize,
+}
+
+pub struct Machine {
+    lua: Lua,
+    pub io: Rc<RefCell<Io>>,
+    replies: mpsc::Receiver<Reply>,
+    http: Rc<RefCell<HttpStats>>,
+}
+
+impl Machine {
+    /// When not `online`, `async.httpGet` requests are dropped and never replied.
+    pub fn new(
+        script: &str,
+        properties: HashMap<String, String>,
+        online: bool,
+    ) -> mlua::Result<Self> {
+        let lua = Lua::new();
+        let io = Rc::new(RefCell::new(Io::default()));
+        let http = Rc::new(RefCell::new(HttpStats::default()));
+        let (tx, replies) = mpsc::channel();
+
+        let input = lua.create_table()?;
+        let io_ = io.clone();
+        input.set(
+            "getNumber",
+            lua.create_function(move |_, i: usize| Ok(get(&io_.borrow().input.numbers, i)))?,
+        )?;
+        let io_ = io.clone();
+        input.set(
+            "getBool",
+            lua.create_function(move |_, i: usize| Ok(get(&io_.borrow().input.bools, i)))?,
+        )?;
+        lua.globals().set("input", input)?;
+
+        let output = lua.create_table()?;
+        let io_ = io.clone();
+        output.set(
+            "setNumber",
+            lua.create_function(move |_, (i, v): (usize, f32)| {
+                let mut io = io_.borrow_mut();
+                if set(&mut io.output.numbers, i, v) {
+                    io.written_numbers |= 1 << (i - 1);
+                }
+                Ok(())
+            })?,
+        )?;
+        let io_ = io.clone();
+        output.set(
+            "setBool",
+            lua.create_function(move |_, (i, v): (usize, bool)| {
+                let mut io = io_.borrow_mut();
+                if set(&mut io.output.bools, i, v) {
+                    io.written_bools |= 1 << (i - 1);
+                }
+                Ok(())
+            })?,
+        )?;
+        lua.globals().set("output", output)?;
+
+        let property = lua.create_table()?;
+        let properties = Rc::new(properties);
+        let p = properties.clone();
+        property.set(
+            "getText",
+            lua.create_function(move |_, name: String| {
+                Ok(p.get(&name).cloned().unwrap_or_default())
+            })?,
+        )?;
+        let p = properties.clone();
+        property.set(
+            "getNumber",
+            lua.create_function(move |_, name: String| {
+                Ok(p.get(&name)
+                    .and_then(|v| v.parse::<f32>().ok())
+                    .unwrap_or_default())
+            })?,
+        )?;
+        let p = properties;
+        property.set(
+            "getBool",
+            lua.create_function(move |_, name: String| {
+                Ok(p.get(&name).map(|v| v == "true").unwrap_or_default())
+            })?,
+        )?;
+        lua.globals().set("property", property)?;
+
+        let async_ = lua.create_table()?;
+        let http_ = http.clone();
+        async_.set(
+            "httpGet",
+            lua.create_function(move |_, (port, request): (u16, String)| {
+                if !online {
+                    log::debug!("dropping request to {}: {}", port, request);
+                    return Ok(());
+                }
+                http_.borrow_mut().requests += 1;
+                let tx = tx.clone();
+                std::thread::spawn(move || {
+                    let response = http_get(port, &request).unwrap_or_else(|e| e.to_string());
+                    tx.send(Reply {
+                        port,
+                        request,
+                        response,
+                    })
+                    .ok();
+                });
+                Ok(())
+            })?,
+        )?;
+        lua.globals().set("async", async_)?;
+
+        lua.load(script).set_name("script").exec()?;
+        Ok(Self {
+            lua,
+            io,
+            replies,
+            http,
+        })
+    }
+
+    /// Delivers finished requests to `httpReply` and runs `onTick`.
+    pub fn tick(&self) -> mlua::Result<()> {
+        while let Ok(reply) = self.replies.try_recv() {
+            self.reply(reply)?;
+        }
+        self.call("onTick")
+    }
+
+    /// Blocks until every request has been answered.
+    pub fn flush(&self) -> mlua::Result<()> {
+        while self.http.borrow().replies < self.http.borrow().requests {
+            match self.replies.recv() {
+                Ok(reply) => self.reply(reply)?,
+                Err(_) => break,
+            }
+        }
+        Ok(())
+    }
+
+    pub fn http_stats(&self) -> std::cell::Ref<'_, HttpStats> {
+        self.http.borrow()
+    }
+
+    fn reply(&self, reply: Reply) -> mlua::Result<()> {
+        {
+            let mut http = self.http.borrow_mut();
+            http.replies += 1;
+            // fragments before the last are answered with the missing ones
+            if !["OK", "MISSING"]
+                .iter()
+                .any(|r| reply.response.starts_with(r))
+            {
+                log::warn!("{} -> {}", reply.request, reply.response);
+                http.failures += 1;
+            }
+        }
+        if let Some(f) = self.lua.globals().get::<_, Option<Function>>("httpReply")? {
+            f.call::<_, ()>((reply.port, reply.request, reply.response))?;
+        }
+        Ok(())
+    }
+
+    fn call(&self, name: &str) -> mlua::Result<()> {
+        if let Some(f) = self.lua.globals().get::<_, Option<Function>>(name)? {
+            f.call::<_, ()>(())?;
+        }
+        Ok(())
+    }
+}
+
+/// 1-based channel like the game; out of range reads default
+fn get<T: Copy + Default>(channels: &[T; CHANNELS], i: usize) -> T {
+    i.checked_sub(1)
+        .and_then(|i| channels.get(i))
+        .copied()
+        .unwrap_or_default()
+}
+
+fn set<T>(channels: &mut [T; CHANNELS], i: usize, v: T) -> bool {
+    match i.checked_sub(1).and_then(|i| channels.get_mut(i)) {
+        Some(c) => {
+            *c = v;
+            true
+        }
+        None => false,
+    }
+}
+
+/// `async.httpGet` only reaches localhost in the game
+pub fn http_get(port: u16, request: &str) -> std::io::Result<String> {
+    let mut stream = TcpStream::connect(("127.0.0.1", port))?;
+    write!(
+        stream,
+        "GET {} HTTP/1.0\r\nHost: localhost:{}\r\n\r\n",
+        request, port
+    )?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    Ok(response
+        .split_once("\r\n\r\n")
+        .map(|(_, body)| body.to_owned())
+        .unwrap_or_default())
+}
@@ -0,0 +1,67 @@
+mod args;
+mod inputs;
+mod machine;
+mod recording;
+mod replay;
+
+use clap::Parser;
+use std::time::{Duration, Instant};
+
+const TICK: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+fn main() {
+    pretty_env_logger::formatted_builder()
+        .filter_level(log::LevelFilter::Info)
+        .init();
+
+    let args = args::Args::parse();
+    let result = match args.command {
+        args::Command::Run(args) => run(args),
+        args::Command::Replay(args) => replay::replay(args),
+    };
+    if let Err(e) = result {
+        log::error!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run(args: args::RunArgs) -> Result<(), String> {
+    let script = std::fs::read_to_string(&args.script)
+        .map_err(|e| format!("{}: {}", args.script.display(), e))?;
+    let machine = machine::Machine::new(&script, args.properties.into_iter().collect(), true)
+        .map_err(|e| e.to_string())?;
+    let mut source = match (&args.csv, &args.inputs) {
+        (Some(csv), _) => inputs::Source::csv(csv)?,
+        (None, Some(inputs)) => inputs::Source::script(inputs, machine.io.clone())?,
+        (None, None) => inputs::Source::None,
+    };
+
+    let start = Instant::now();
+    let mut tick = 0;
+    while args.ticks.is_none_or(|t| tick < t) {
+        if !source.apply(tick, &machine.io)? {
+            break;
+        }
+        machine.tick().map_err(|e| e.to_string())?;
+        tick += 1;
+        if !args.fast {
+            if let Some(d) = (start + TICK * tick as u32).checked_duration_since(Instant::now()) {
+                std::thread::sleep(d);
+            }
+        }
+    }
+    machine.flush().map_err(|e| e.to_string())?;
+
+    let http = machine.http_stats();
+    log::info!(
+        "{} ticks, {} requests, {} replies, {} failed",
+        tick,
+        http.requests,
+        http.replies,
+        http.failures
+    );
+    if http.failures > 0 {
+        return Err(format!("{} requests failed", http.failures));
+    }
+    Ok(())
+}
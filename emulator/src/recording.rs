@@ -0,0 +1,93 @@
+//! Recordings from `Values::save_csv` of the viewer or `/download.json` of the server.
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufRead, BufReader, Write},
+    path::Path,
+};
+
+/// Series aligned to their last sample, `None` before a key starts
+#[derive(Debug, Default)]
+pub struct Recording {
+    pub keys: Vec<String>,
+    pub columns: Vec<Vec<Option<f32>>>,
+}
+
+impl Recording {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        if path.extension().is_some_and(|e| e == "json") {
+            #[derive(Deserialize)]
+            struct Dump {
+                values: BTreeMap<String, Vec<Option<f32>>>,
+            }
+            let dump: Dump = serde_json::from_reader(BufReader::new(file))
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+            Ok(Self::aligned(dump.values.into_iter()))
+        } else {
+            Self::load_csv(BufReader::new(file)).map_err(|e| format!("{}: {}", path.display(), e))
+        }
+    }
+
+    fn load_csv(reader: impl BufRead) -> Result<Self, String> {
+        let mut lines = reader.lines();
+        let header = match lines.next() {
+            Some(header) => header.map_err(|e| e.to_string())?,
+            None => return Err("empty file".into()),
+        };
+        let keys: Vec<String> = header.split(',').map(|k| k.to_owned()).collect();
+        let mut columns = vec![vec![]; keys.len()];
+        for line in lines {
+            let line = line.map_err(|e| e.to_string())?;
+            let mut values = line.split(',');
+            for column in columns.iter_mut() {
+                column.push(values.next().and_then(|v| v.parse().ok()));
+            }
+        }
+        Ok(Self { keys, columns })
+    }
+
+    fn aligned(series: impl Iterator<Item = (String, Vec<Option<f32>>)>) -> Self {
+        let (keys, mut columns): (Vec<_>, Vec<_>) = series.unzip();
+        let len = columns.iter().map(|c| c.len()).max().unwrap_or_default();
+        for column in columns.iter_mut() {
+            column.splice(0..0, std::iter::repeat_n(None, len - column.len()));
+        }
+        Self { keys, columns }
+    }
+
+    pub fn len(&self) -> usize {
+        self.columns.first().map(|c| c.len()).unwrap_or_default()
+    }
+
+    pub fn column(&self, key: &str) -> Option<&[Option<f32>]> {
+        self.keys
+            .iter()
+            .position(|k| k == key)
+            .map(|i| self.columns[i].as_slice())
+    }
+
+    pub fn push(&mut self, key: String, column: Vec<Option<f32>>) {
+        self.keys.push(key);
+        self.columns.push(column);
+    }
+
+    /// Same layout as `Values::save_csv` of the viewer
+    pub fn save_csv(&self, writer: impl Write) -> std::io::Result<()> {
+        let mut writer = std::io::BufWriter::new(writer);
+        writeln!(writer, "{}", self.keys.join(","))?;
+        for row in 0..self.len() {
+            for (i, column) in self.columns.iter().enumerate() {
+                if i > 0 {
+                    writer.write_all(b",")?;
+                }
+                if let Some(v) = column[row] {
+                    write!(writer, "{}", v)?;
+                }
+            }
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()
+    }
+}
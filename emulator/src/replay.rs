@@ -0,0 +1,133 @@
+//! Runs a microcontroller script against a recording, adding its outputs as new keys.
+use crate::{args::ReplayArgs, machine, recording::Recording};
+use std::fs::File;
+
+/// Ticks per `/push` request
+const PUSH_BATCH: usize = 60;
+
+enum Input {
+    Number(usize),
+    Bool(usize),
+}
+
+pub fn replay(args: ReplayArgs) -> Result<(), String> {
+    let script = std::fs::read_to_string(&args.script)
+        .map_err(|e| format!("{}: {}", args.script.display(), e))?;
+    let machine = machine::Machine::new(&script, args.properties.into_iter().collect(), false)
+        .map_err(|e| e.to_string())?;
+    let mut recording = Recording::load(&args.recording)?;
+
+    let mut inputs = vec![];
+    for (key, channel) in &args.maps {
+        let column = recording
+            .column(key)
+            .ok_or_else(|| format!("no key {:?} in the recording", key))?;
+        let input = match parse_channel(channel)? {
+            (true, i) => Input::Number(i),
+            (false, i) => Input::Bool(i),
+        };
+        inputs.push((column, input));
+    }
+
+    let len = recording.len();
+    let mut numbers = vec![vec![None; len]; machine::CHANNELS];
+    let mut bools = vec![vec![None; len]; machine::CHANNELS];
+    for row in 0..len {
+        {
+            let mut io = machine.io.borrow_mut();
+            // keep the previous input where a key has no sample yet
+            for (column, input) in &inputs {
+                match (column[row], input) {
+                    (Some(v), Input::Number(i)) => io.input.numbers[i - 1] = v,
+                    (Some(v), Input::Bool(i)) => io.input.bools[i - 1] = v != 0.0,
+                    (None, _) => {}
+                }
+            }
+        }
+        machine.tick().map_err(|e| format!("tick {}: {}", row, e))?;
+        let io = machine.io.borrow();
+        for i in 0..machine::CHANNELS {
+            if io.written_numbers & (1 << i) != 0 {
+                numbers[i][row] = Some(io.output.numbers[i]);
+            }
+            if io.written_bools & (1 << i) != 0 {
+                bools[i][row] = Some(if io.output.bools[i] { 1.0 } else { 0.0 });
+            }
+        }
+    }
+
+    let written = machine.io.borrow();
+    for (prefix, columns, mask) in [
+        ("N", numbers, written.written_numbers),
+        ("B", bools, written.written_bools),
+    ] {
+        for (i, column) in columns.into_iter().enumerate() {
+            if mask & (1 << i) == 0 {
+                continue;
+            }
+            let channel = format!("{}{}", prefix, i + 1);
+            let key = args
+                .outputs
+                .iter()
+                .find(|(c, _)| *c == channel)
+                .map(|(_, k)| k.clone())
+                .unwrap_or_else(|| format!("{}.{}", args.prefix, channel));
+            recording.push(key, column);
+        }
+    }
+
+    match &args.out {
+        Some(path) => File::create(path)
+            .and_then(|f| recording.save_csv(f))
+            .map_err(|e| format!("{}: {}", path.display(), e))?,
+        None if args.push.is_none() => recording
+            .save_csv(std::io::stdout().lock())
+            .map_err(|e| e.to_string())?,
+        None => {}
+    }
+    if let Some(port) = args.push {
+        push(&recording, port, args.token.as_deref())?;
+    }
+    Ok(())
+}
+
+/// `N<channel>` or `B<channel>`, returning whether it is a number
+fn parse_channel(s: &str) -> Result<(bool, usize), String> {
+    let channel = s
+        .get(1..)
+        .and_then(|i| i.parse().ok())
+        .filter(|i| (1..=machine::CHANNELS).contains(i));
+    match (s.chars().next(), channel) {
+        (Some('N'), Some(i)) => Ok((true, i)),
+        (Some('B'), Some(i)) => Ok((false, i)),
+        _ => Err(format!("expected N1..N32 or B1..B32: {}", s)),
+    }
+}
+
+/// Sends every key through `/push` of the server
+fn push(recording: &Recording, port: u16, token: Option<&str>) -> Result<(), String> {
+    for start in (0..recording.len()).step_by(PUSH_BATCH) {
+        let end = (start + PUSH_BATCH).min(recording.len());
+        let mut query = vec![];
+        for (key, column) in recording.keys.iter().zip(&recording.columns) {
+            // gaps after the first sample are sent as NaN to keep keys aligned
+            let first = column
+                .iter()
+                .position(|v| v.is_some())
+                .unwrap_or(column.len());
+            for v in &column[start.max(first)..end.max(first)] {
+                query.push((key.as_str(), v.unwrap_or(f32::NAN).to_string()));
+            }
+        }
+        if let Some(token) = token {
+            query.push(("token", token.to_owned()));
+        }
+        let query = serde_urlencoded::to_string(&query).map_err(|e| e.to_string())?;
+        let response =
+            machine::http_get(port, &format!("/push?{}", query)).map_err(|e| e.to_string())?;
+        if response != "OK" {
+            return Err(format!("failed to push: {}", response));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,171 @@
+//! End-to-end tests running scripts in the emulator against a server.
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    sync::OnceLock,
+    thread,
+    time::{Duration, Instant},
+};
+
+const EMULATOR: &str = env!("CARGO_BIN_EXE_sw_logger_emulator");
+
+/// Server binary of the workspace, built next to the emulator
+fn server_binary() -> &'static Path {
+    static BINARY: OnceLock<PathBuf> = OnceLock::new();
+    BINARY.get_or_init(|| {
+        let dir = Path::new(EMULATOR).parent().unwrap();
+        let mut build = Command::new(env!("CARGO"));
+        build.args([
+            "build",
+            "-p",
+            "sw_logger_server",
+            "--bin",
+            "sw_logger_server",
+        ]);
+        if dir.ends_with("release") {
+            build.arg("--release");
+        }
+        assert!(
+            build.status().unwrap().success(),
+            "failed to build the server"
+        );
+        dir.join(format!("sw_logger_server{}", std::env::consts::EXE_SUFFIX))
+    })
+}
+
+struct Server {
+    process: Child,
+    port: u16,
+}
+
+impl Server {
+    fn start() -> Self {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let process = Command::new(server_binary())
+            .args(["-p", &port.to_string()])
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let server = Self { process, port };
+        let start = Instant::now();
+        while server.try_get("/status").is_err() {
+            assert!(
+                start.elapsed() < Duration::from_secs(10),
+                "server didn't start"
+            );
+            thread::sleep(Duration::from_millis(50));
+        }
+        server
+    }
+
+    fn try_get(&self, path: &str) -> std::io::Result<String> {
+        let mut stream = TcpStream::connect(("127.0.0.1", self.port))?;
+        write!(stream, "GET {} HTTP/1.0\r\n\r\n", path)?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        Ok(response
+            .split_once("\r\n\r\n")
+            .map(|(_, body)| body.to_owned())
+            .unwrap_or_default())
+    }
+
+    /// Samples of a key from tick 0
+    fn series(&self, key: &str) -> Vec<f32> {
+        let body = self.try_get(&format!("/series/{}", key)).unwrap();
+        let series: serde_json::Value = serde_json::from_str(&body).expect(&body);
+        assert_eq!(series["start"], 0, "{}", body);
+        serde_json::from_value(series["values"].clone()).unwrap()
+    }
+
+    /// Runs `script` for `ticks` ticks with the inputs of `inputs`, at 60
+    /// ticks/s so replies arrive like in the game
+    fn emulate(&self, script: &str, inputs: &str, properties: &[&str], ticks: u64) {
+        let dir = std::env::temp_dir().join(format!("sw_logger_ingest_{}", self.port));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("script.lua"), script).unwrap();
+        std::fs::write(dir.join("inputs.lua"), inputs).unwrap();
+        let mut run = Command::new(EMULATOR);
+        run.arg("run")
+            .arg(dir.join("script.lua"))
+            .arg("--inputs")
+            .arg(dir.join("inputs.lua"))
+            .args(["-t", &ticks.to_string()]);
+        for property in properties {
+            run.args(["-p", property]);
+        }
+        let status = run.status().unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+        assert!(status.success(), "emulator failed");
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.process.kill().ok();
+        self.process.wait().ok();
+    }
+}
+
+/// Ticks `0..len` of a signal must be sent in order without loss
+fn assert_signal(values: &[f32], signal: impl Fn(usize) -> f32) {
+    assert!(!values.is_empty());
+    for (tick, v) in values.iter().enumerate() {
+        assert_eq!(*v, signal(tick), "tick {}", tick);
+    }
+}
+
+#[test]
+fn example_script() {
+    let server = Server::start();
+    let script = std::fs::read_to_string(
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("../example/metrics.lua"),
+    )
+    .unwrap()
+    .replace(
+        "async.httpGet(8080,",
+        &format!("async.httpGet({},", server.port),
+    );
+    let inputs = r#"function inputs(tick)
+        input.setBool(1, true)
+        input.setNumber(1, tick)
+        input.setNumber(2, tick * 0.5)
+    end"#;
+    server.emulate(&script, inputs, &["label_1=a", "label_2=b"], 300);
+
+    let a = server.series("a");
+    // the last batch may still be collecting
+    assert!(a.len() > 250, "{} samples", a.len());
+    assert_signal(&a, |t| t as f32);
+    assert_signal(&server.series("b"), |t| t as f32 * 0.5);
+}
+
+#[test]
+fn fragmented_script() {
+    let server = Server::start();
+    let output = Command::new(server_binary())
+        .args(["gen-lua", "-c", "x:n:0.5", "-c", "y", "-c", "on:b"])
+        .args(["--part-size", "30", "-p", &server.port.to_string()])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let script = String::from_utf8(output.stdout).unwrap();
+    // every batch is split, the parts before the last are answered with MISSING
+    let inputs = r#"function inputs(tick)
+        input.setNumber(1, tick * 0.5)
+        input.setNumber(2, tick / 3)
+        input.setBool(1, tick % 2 == 0)
+    end"#;
+    server.emulate(&script, inputs, &[], 300);
+
+    let x = server.series("x");
+    assert!(x.len() > 250, "{} samples", x.len());
+    assert_signal(&x, |t| t as f32 * 0.5);
+    assert_signal(&server.series("y"), |t| t as f32 / 3.0);
+    assert_signal(&server.series("on"), |t| (t % 2 == 0) as u8 as f32);
+}
@@ -14,24 +14,39 @@ for i = 1, 32, 1 do
     end
 end
 
+token = property.getText("token")
+pending = 0
+
 values = {}
-tick = 0
+samples = 0
+-- recommended by the server in every reply
+batch = 10
+-- ticks since the last reply, reported as `w` so the server can measure latency
+waited = 0
+-- give up on replies after this many ticks
+REPLY_TIMEOUT = 300
+-- output number channels set by `c=<channel>=<value>` commands of the server
+outputs = {}
 
 function onTick()
-    if not input.getBool(1) then
-        if #values == 0 then
-            return
-        end
-        tick = 1
+    waited = waited + 1
+    for channel, value in pairs(outputs) do
+        output.setNumber(channel, value)
     end
-    for i = 1, 32, 1 do
-        if labels[i] ~= "" and labels[i] ~= nil then
-            values[i] = values[i] or {}
-            table.insert(values[i], input.getNumber(i))
+    local enabled = input.getBool(1)
+    if enabled then
+        for i = 1, 32, 1 do
+            if labels[i] ~= "" and labels[i] ~= nil then
+                values[i] = values[i] or {}
+                table.insert(values[i], input.getNumber(i))
+            end
         end
+        samples = samples + 1
+    end
+    if pending > 0 and waited > REPLY_TIMEOUT then
+        pending = 0
     end
-    tick = tick - 1
-    if tick > 0 then
+    if samples == 0 or pending > 0 or (enabled and samples < batch) then
         return
     end
 
@@ -53,10 +68,33 @@ function onTick()
             table.insert(buf, ('>Bf'):pack(0xca, v))
         end
     end
-    query = "/p?" .. encode64(table.concat(buf))
+    send(table.concat(buf))
     values = {}
-    async.httpGet(8080, query)
-    tick = 10
+    samples = 0
+end
+
+-- bytes per request; a multiple of 3 so that only the last part is padded
+PART_SIZE = 180
+message_id = 0
+seq = 0
+-- differs between loads, so the server can tell a restart from duplicates
+run = math.random(1 << 30)
+
+function send(s)
+    local count = (#s + PART_SIZE - 1) // PART_SIZE
+    message_id = (message_id + 1) % 65536
+    seq = (seq + 1) % 65536
+    for i = 0, count - 1, 1 do
+        local query = "/p?" .. encode64(s:sub(i * PART_SIZE + 1, (i + 1) * PART_SIZE)) .. "&r=" .. run .. "&s=" .. seq .. "&w=" .. waited
+        if count > 1 then
+            query = query .. string.format("&m=%d&i=%d&n=%d", message_id, i, count)
+        end
+        if #token > 0 then
+            query = query .. "&token=" .. token
+        end
+        async.httpGet(8080, query)
+        pending = pending + 1
+    end
 end
 
 function encode64(s)
@@ -74,5 +112,17 @@ function onDraw()
 end
 
 function httpReply(port, request_body, response_body)
-    tick = 0
+    pending = math.max(pending - 1, 0)
+    if pending == 0 then
+        waited = 0
+    end
+    -- OK b=<batch size> r=<recording> t=<server tick> [c=<command>]
+    local b = response_body:match("^OK b=(%d+)")
+    if b then
+        batch = tonumber(b)
+    end
+    local channel, value = response_body:match(" c=(%d+)=(%S+)")
+    if channel then
+        outputs[tonumber(channel)] = tonumber(value)
+    end
 end
@@ -0,0 +1,164 @@
+-- Same as metrics.lua but sends the version 2 payload (`&v=2`).
+-- Set `scale_N` to quantize channel N as `offset_N + scale_N * q`,
+-- leave it 0 to send plain float32.
+BASE64_CHARS = {
+    [0] = 'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L', 'M', 'N', 'O', 'P',
+    'Q', 'R', 'S', 'T', 'U', 'V', 'W', 'X', 'Y', 'Z', 'a', 'b', 'c', 'd', 'e', 'f',
+    'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o', 'p', 'q', 'r', 's', 't', 'u', 'v',
+    'w', 'x', 'y', 'z', '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', '-', '_'
+}
+
+labels = {}
+scales = {}
+offsets = {}
+count = 0
+for i = 1, 32, 1 do
+    labels[i] = property.getText(string.format("label_%d", i));
+    scales[i] = property.getNumber(string.format("scale_%d", i));
+    offsets[i] = property.getNumber(string.format("offset_%d", i));
+    if #labels[i] > 0 then
+        count = count + 1
+    end
+end
+
+token = property.getText("token")
+pending = 0
+
+values = {}
+samples = 0
+-- recommended by the server in every reply
+batch = 10
+-- ticks since the last reply, reported as `w` so the server can measure latency
+waited = 0
+-- give up on replies after this many ticks
+REPLY_TIMEOUT = 300
+-- output number channels set by `c=<channel>=<value>` commands of the server
+outputs = {}
+
+function onTick()
+    waited = waited + 1
+    for channel, value in pairs(outputs) do
+        output.setNumber(channel, value)
+    end
+    local enabled = input.getBool(1)
+    if enabled then
+        for i = 1, 32, 1 do
+            if labels[i] ~= "" and labels[i] ~= nil then
+                values[i] = values[i] or {}
+                table.insert(values[i], input.getNumber(i))
+            end
+        end
+        samples = samples + 1
+    end
+    if pending > 0 and waited > REPLY_TIMEOUT then
+        pending = 0
+    end
+    if samples == 0 or pending > 0 or (enabled and samples < batch) then
+        return
+    end
+
+    buf = {}
+    if count < 16 then
+        table.insert(buf, ('>B'):pack(0x80 + count))
+    else
+        table.insert(buf, ('>BI2'):pack(0xde, count))
+    end
+    for i, value in pairs(values) do
+        local key = labels[i]
+        table.insert(buf, ('>B'):pack(0xa0 + #key) .. key)
+        local format, start, data = encode_channel(value, scales[i], offsets[i])
+        data = table.concat(data)
+        table.insert(buf, ('>BBBfBfBi4'):pack(0x95, format, 0xca, scales[i], 0xca, offsets[i], 0xd2, start))
+        if #data < 256 then
+            table.insert(buf, ('>BB'):pack(0xc4, #data) .. data)
+        else
+            table.insert(buf, ('>BI2'):pack(0xc5, #data) .. data)
+        end
+    end
+    send(table.concat(buf))
+    values = {}
+    samples = 0
+end
+
+-- returns format, start and the encoded samples
+function encode_channel(value, scale, offset)
+    local data = {}
+    if scale == 0 then
+        for _, v in ipairs(value) do
+            table.insert(data, ('>f'):pack(v))
+        end
+        return 0x00, 0, data
+    end
+    local q = {}
+    local delta = true
+    for i, v in ipairs(value) do
+        q[i] = math.floor((v - offset) / scale + 0.5)
+        if i > 1 and (q[i] - q[i - 1] < -128 or q[i] - q[i - 1] > 127) then
+            delta = false
+        end
+    end
+    if delta and #q > 0 then
+        for i = 2, #q, 1 do
+            table.insert(data, ('>i1'):pack(q[i] - q[i - 1]))
+        end
+        return 0x11, q[1], data
+    end
+    for _, v in ipairs(q) do
+        table.insert(data, ('>i2'):pack(math.max(-32768, math.min(32767, v))))
+    end
+    return 0x02, 0, data
+end
+
+-- bytes per request; a multiple of 3 so that only the last part is padded
+PART_SIZE = 180
+message_id = 0
+seq = 0
+-- differs between loads, so the server can tell a restart from duplicates
+run = math.random(1 << 30)
+
+function send(s)
+    local count = (#s + PART_SIZE - 1) // PART_SIZE
+    message_id = (message_id + 1) % 65536
+    seq = (seq + 1) % 65536
+    for i = 0, count - 1, 1 do
+        local query = "/p?" .. encode64(s:sub(i * PART_SIZE + 1, (i + 1) * PART_SIZE)) .. "&v=2&r=" .. run .. "&s=" .. seq .. "&w=" .. waited
+        if count > 1 then
+            query = query .. string.format("&m=%d&i=%d&n=%d", message_id, i, count)
+        end
+        if #token > 0 then
+            query = query .. "&token=" .. token
+        end
+        async.httpGet(8080, query)
+        pending = pending + 1
+    end
+end
+
+function encode64(s)
+    local p = 2 - (#s - 1) % 3
+    return (s .. string.rep("\0", p)):gsub("...", function(cs)
+        local c1, c2, c3 = string.byte(cs, 1, 3)
+        return BASE64_CHARS[c1 >> 2] ..
+            BASE64_CHARS[(c1 & 0x03) << 4 | c2 >> 4] ..
+            BASE64_CHARS[(c2 & 0x0f) << 2 | c3 >> 6] ..
+            BASE64_CHARS[c3 & 0x3f]
+    end)
+end
+
+function onDraw()
+end
+
+function httpReply(port, request_body, response_body)
+    pending = math.max(pending - 1, 0)
+    if pending == 0 then
+        waited = 0
+    end
+    -- OK b=<batch size> r=<recording> t=<server tick> [c=<command>]
+    local b = response_body:match("^OK b=(%d+)")
+    if b then
+        batch = tonumber(b)
+    end
+    local channel, value = response_body:match(" c=(%d+)=(%S+)")
+    if channel then
+        outputs[tonumber(channel)] = tonumber(value)
+    end
+end
@@ -0,0 +1,73 @@
+//! Push latency while downloads and WebSocket clients read the same keys.
+//!
+//! `cargo bench -p sw_logger_server` starts the server on a free port and
+//! runs `simulate` against it, once paced like the game and once pushing as
+//! fast as the server replies. Arguments after `--` are passed to `simulate`.
+//!
+//! Only the paced run compares downloads/s between builds: when pushing as
+//! fast as possible, a server accepting more pushes leaves less CPU to reads.
+use std::{
+    net::{TcpListener, TcpStream},
+    process::{Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+const SERVER: &str = env!("CARGO_BIN_EXE_sw_logger_server");
+
+fn main() {
+    let extra: Vec<String> = std::env::args()
+        .skip(1)
+        .filter(|arg| arg != "--bench")
+        .collect();
+    for (name, fast) in [("paced", false), ("fast", true)] {
+        println!("{}:", name);
+        // a fresh server, so the runs see the same buffers and sources
+        let (mut server, server_url) = start_server();
+        let mut simulate = Command::new(SERVER);
+        simulate
+            .args(["simulate", "--server", &server_url])
+            .args(["--keys", "200", "--sources", "8", "--duration", "10"])
+            .args(["--downloaders", "4", "--ws-clients", "20"])
+            .args(["--download", "/download.json"])
+            .args(["--download", "/download.csv"])
+            .args(["--download", "/download.jsonl"])
+            .args(["--download", "/download.parquet"])
+            .args(&extra);
+        if fast {
+            simulate.arg("--fast");
+        }
+        let output = simulate.output().unwrap();
+        let log = String::from_utf8_lossy(&output.stderr);
+        // the per-second reports are noise next to the total
+        for line in log.lines().skip_while(|l| !l.ends_with("> total")).skip(1) {
+            println!("  {}", line.split("> ").last().unwrap_or(line));
+        }
+        server.kill().ok();
+        server.wait().ok();
+        assert!(output.status.success(), "simulate failed:\n{}", log);
+    }
+}
+
+/// Starts a server on a free port and returns it with its URL
+fn start_server() -> (Child, String) {
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let server = Command::new(SERVER)
+        .args(["-p", &port.to_string()])
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let start = Instant::now();
+    while TcpStream::connect(("127.0.0.1", port)).is_err() {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "server didn't start"
+        );
+        thread::sleep(Duration::from_millis(50));
+    }
+    (server, format!("http://127.0.0.1:{}", port))
+}
@@ -0,0 +1,315 @@
+//! Alert rules evaluated on every push, with stale data checked periodically.
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    io::BufWriter,
+    path::PathBuf,
+    str::FromStr,
+    sync::mpsc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+const TICKS_PER_SECOND: f32 = 60.0;
+/// Interval of [`Alerts::check_stale`]
+pub const STALE_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Measure {
+    Value,
+    /// change per second between consecutive samples
+    Rate,
+    /// seconds since the last push of the key
+    Stale,
+}
+
+/// `key>value`, `key<value`, `rate(key)>value` or `stale(key)>seconds`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    key: String,
+    measure: Measure,
+    above: bool,
+    threshold: f32,
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (lhs, threshold) = s
+            .split_once(['>', '<'])
+            .ok_or_else(|| format!("expected key>value or key<value: {}", s))?;
+        let above = s.as_bytes()[lhs.len()] == b'>';
+        let threshold = threshold
+            .trim()
+            .parse()
+            .map_err(|e| format!("threshold {:?}: {}", threshold, e))?;
+        let lhs = lhs.trim();
+        let (measure, key) = match lhs.split_once('(') {
+            Some((f, key)) => {
+                let key = key
+                    .strip_suffix(')')
+                    .ok_or_else(|| format!("missing ) in {}", s))?;
+                match f {
+                    "rate" => (Measure::Rate, key),
+                    "stale" if above => (Measure::Stale, key),
+                    "stale" => return Err("stale data is only checked with >".into()),
+                    f => return Err(format!("unknown function {}, expected rate or stale", f)),
+                }
+            }
+            None => (Measure::Value, lhs),
+        };
+        if key.is_empty() {
+            return Err(format!("missing key in {}", s));
+        }
+        Ok(Rule {
+            key: key.to_owned(),
+            measure,
+            above,
+            threshold,
+        })
+    }
+}
+
+impl std::fmt::Display for Rule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let op = if self.above { '>' } else { '<' };
+        match self.measure {
+            Measure::Value => write!(f, "{}{}{}", self.key, op, self.threshold),
+            Measure::Rate => write!(f, "rate({}){}{}", self.key, op, self.threshold),
+            Measure::Stale => write!(f, "stale({}){}{}", self.key, op, self.threshold),
+        }
+    }
+}
+
+impl Rule {
+    fn beyond(&self, v: f32) -> bool {
+        if self.above {
+            v > self.threshold
+        } else {
+            v < self.threshold
+        }
+    }
+}
+
+/// Broadcast as `{"alert": ...}` when a rule starts or stops matching
+#[derive(Debug, Clone, Serialize)]
+pub struct Alert {
+    pub rule: String,
+    pub key: String,
+    pub value: f32,
+    pub active: bool,
+}
+
+#[derive(Debug)]
+struct State {
+    rule: Rule,
+    active: bool,
+    last: Option<f32>,
+    last_seen: Option<Instant>,
+    /// samples in a row disagreeing with `active`
+    pending: usize,
+    fired: Option<Instant>,
+}
+
+impl State {
+    /// Switches to `active` once `hold` samples in a row agreed, firing at
+    /// most once per `interval`
+    fn update(
+        &mut self,
+        active: bool,
+        value: f32,
+        hold: usize,
+        interval: Duration,
+    ) -> Option<Alert> {
+        if active == self.active {
+            self.pending = 0;
+            return None;
+        }
+        self.pending += 1;
+        if self.pending < hold || (active && self.fired.is_some_and(|t| t.elapsed() < interval)) {
+            return None;
+        }
+        self.pending = 0;
+        self.active = active;
+        if active {
+            self.fired = Some(Instant::now());
+        }
+        Some(Alert {
+            rule: self.rule.to_string(),
+            key: self.rule.key.clone(),
+            value,
+            active,
+        })
+    }
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct Options {
+    /// `key>value`, `key<value`, `rate(key)>value` (per second) or
+    /// `stale(key)>seconds`
+    #[arg(long = "alert")]
+    pub rules: Vec<Rule>,
+    /// Seconds a rule has to match or stop matching before it fires or clears
+    #[arg(long, default_value = "0.5")]
+    pub alert_hold: f32,
+    /// Seconds before a rule that cleared can fire again
+    #[arg(long, default_value = "10")]
+    pub alert_interval: f32,
+    /// Append fired and cleared alerts to this file
+    #[arg(long)]
+    pub alert_log: Option<PathBuf>,
+    /// Run this shell command for every alert with ALERT_RULE, ALERT_KEY,
+    /// ALERT_VALUE and ALERT_ACTIVE set
+    #[arg(long)]
+    pub alert_command: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Alerts {
+    rules: Vec<State>,
+    /// samples of `--alert-hold`
+    hold: usize,
+    interval: Duration,
+    /// lines for the thread appending to `--alert-log`
+    log: Option<mpsc::Sender<String>>,
+    command: Option<String>,
+}
+
+impl Alerts {
+    pub fn new(options: &Options) -> Result<Self, String> {
+        if !(options.alert_hold >= 0.0 && options.alert_interval >= 0.0) {
+            return Err("--alert-hold and --alert-interval must not be negative".into());
+        }
+        let interval = Duration::try_from_secs_f32(options.alert_interval)
+            .map_err(|e| format!("--alert-interval: {}", e))?;
+        let log = match &options.alert_log {
+            Some(path) => {
+                let file = std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|e| format!("{}: {}", path.display(), e))?;
+                let (tx, rx) = mpsc::channel();
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    crate::sink::write_lines(BufWriter::new(file), &path, rx)
+                });
+                Some(tx)
+            }
+            None => None,
+        };
+        Ok(Self {
+            rules: options
+                .rules
+                .iter()
+                .map(|rule| State {
+                    rule: rule.clone(),
+                    active: false,
+                    last: None,
+                    last_seen: None,
+                    pending: 0,
+                    fired: None,
+                })
+                .collect(),
+            hold: ((options.alert_hold * TICKS_PER_SECOND) as usize).max(1),
+            interval,
+            log,
+            command: options.alert_command.clone(),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn check(&mut self, batch: &HashMap<String, Vec<f32>>) -> Vec<Alert> {
+        let mut alerts = vec![];
+        for state in &mut self.rules {
+            // stale data clears on the first push
+            let hold = match state.rule.measure {
+                Measure::Stale => 1,
+                _ => self.hold,
+            };
+            let Some(values) = batch.get(&state.rule.key) else {
+                // a key that never arrives is stale from the first push on
+                if state.rule.measure == Measure::Stale {
+                    state.last_seen.get_or_insert_with(Instant::now);
+                }
+                continue;
+            };
+            state.last_seen = Some(Instant::now());
+            for &v in values.iter().filter(|v| !v.is_nan()) {
+                let active = match state.rule.measure {
+                    Measure::Value => state.rule.beyond(v),
+                    Measure::Rate => state
+                        .last
+                        .is_some_and(|last| state.rule.beyond((v - last) * TICKS_PER_SECOND)),
+                    Measure::Stale => false,
+                };
+                state.last = Some(v);
+                alerts.extend(state.update(active, v, hold, self.interval));
+            }
+        }
+        alerts
+    }
+
+    pub fn check_stale(&mut self) -> Vec<Alert> {
+        let mut alerts = vec![];
+        for state in &mut self.rules {
+            let (Measure::Stale, Some(last_seen)) = (state.rule.measure, state.last_seen) else {
+                continue;
+            };
+            let seconds = last_seen.elapsed().as_secs_f32();
+            if seconds > state.rule.threshold {
+                alerts.extend(state.update(true, seconds, 1, self.interval));
+            }
+        }
+        alerts
+    }
+
+    /// Logs the alert, appends it to the log and runs the command
+    pub fn act(&self, alert: &Alert) {
+        let event = if alert.active { "FIRED" } else { "CLEARED" };
+        log::warn!(
+            "alert {} {} ({}={})",
+            event,
+            alert.rule,
+            alert.key,
+            alert.value
+        );
+        if let Some(log) = &self.log {
+            let time = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            let line = format!(
+                "{} {} {} {}={}",
+                time, event, alert.rule, alert.key, alert.value
+            );
+            if log.send(line).is_err() {
+                log::error!("alert log writer stopped");
+            }
+        }
+        if let Some(command) = &self.command {
+            let (shell, flag) = if cfg!(windows) {
+                ("cmd", "/C")
+            } else {
+                ("sh", "-c")
+            };
+            let child = tokio::process::Command::new(shell)
+                .arg(flag)
+                .arg(command)
+                .env("ALERT_RULE", &alert.rule)
+                .env("ALERT_KEY", &alert.key)
+                .env("ALERT_VALUE", alert.value.to_string())
+                .env("ALERT_ACTIVE", (alert.active as u8).to_string())
+                .spawn();
+            match child {
+                Ok(mut child) => {
+                    tokio::spawn(async move { child.wait().await });
+                }
+                Err(e) => log::error!("failed to run alert command: {}", e),
+            }
+        }
+    }
+}
@@ -1,11 +1,70 @@
-use clap::Parser;
-use std::net::Ipv4Addr;
+use clap::{Parser, Subcommand};
+use std::{net::Ipv4Addr, path::PathBuf};
 
 #[derive(Debug, Parser)]
 #[command(author, version)]
 pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
     #[arg(short, long, default_value = "127.0.0.1")]
     pub ip: Ipv4Addr,
     #[arg(short, long, default_value = "8080")]
     pub port: u16,
+    /// Token required as `?token=` on ingestion routes (/push, /p)
+    #[arg(long)]
+    pub write_token: Option<String>,
+    /// Token required as `?token=` or bearer token on read routes (/socket,
+    /// /download.json, /grafana)
+    #[arg(long)]
+    pub read_token: Option<String>,
+    /// Seconds to keep incomplete split messages sent to /p
+    #[arg(long, default_value = "5")]
+    pub fragment_timeout: u64,
+    /// Also serve HTTPS/WSS on this port
+    #[arg(long)]
+    pub tls_port: Option<u16>,
+    #[arg(long, default_value = "0.0.0.0")]
+    pub tls_ip: Ipv4Addr,
+    /// PEM certificate; a self-signed one is generated when omitted
+    #[arg(long, requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+    /// PEM private key for --tls-cert
+    #[arg(long, requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+    /// Extra host names for the self-signed certificate
+    #[arg(long)]
+    pub tls_name: Vec<String>,
+    /// Only store pushes after /recording/start or /recording/arm
+    #[arg(long)]
+    pub idle: bool,
+    /// Seconds without pushes after which a new session starts, 0 to never
+    #[arg(long, default_value = "30")]
+    pub session_gap: u64,
+    #[command(flatten)]
+    pub alerts: crate::alerts::Options,
+    #[command(flatten)]
+    pub sinks: crate::sink::Options,
+    #[command(flatten)]
+    pub mqtt: crate::mqtt::Options,
+    #[command(flatten)]
+    pub relay: crate::relay::Options,
+    /// Also store samples in this SQLite database
+    #[arg(long)]
+    pub db: Option<PathBuf>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Print a minified microcontroller script pushing the given channels
+    GenLua(crate::luagen::Options),
+    /// Print a microcontroller XML with the logger script and label properties
+    GenMc(crate::mcgen::Options),
+    /// Push synthetic signals to a running server and report the throughput
+    Simulate(crate::simulate::Options),
+    /// Print a session of a database as JSON like /download.json
+    Export(crate::db::ExportOptions),
+    /// Write a session of a database as Apache Parquet
+    ExportParquet(crate::export::Options),
+    /// Store a JSON dump as a new session of a database
+    Import(crate::db::ImportOptions),
 }
@@ -0,0 +1,51 @@
+use crate::AppState;
+use axum::{
+    extract::State,
+    http::{header, Request, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use std::sync::Arc;
+
+#[derive(Debug, Default)]
+pub struct Tokens {
+    pub read: Option<String>,
+    pub write: Option<String>,
+}
+
+pub async fn require_read<B>(
+    State(state): State<Arc<AppState>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Result<Response, StatusCode> {
+    check(state.tokens.read.as_deref(), &req)?;
+    Ok(next.run(req).await)
+}
+
+pub async fn require_write<B>(
+    State(state): State<Arc<AppState>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Result<Response, StatusCode> {
+    check(state.tokens.write.as_deref(), &req)?;
+    Ok(next.run(req).await)
+}
+
+fn check<B>(expected: Option<&str>, req: &Request<B>) -> Result<(), StatusCode> {
+    let Some(expected) = expected else {
+        return Ok(());
+    };
+    let query = req.uri().query().unwrap_or_default();
+    let pairs = serde_urlencoded::from_str::<Vec<(String, String)>>(query).unwrap_or_default();
+    // clients like Grafana can only send a header
+    let bearer = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    if bearer == Some(expected) || pairs.iter().any(|(k, v)| k == "token" && v == expected) {
+        Ok(())
+    } else {
+        Err(StatusCode::UNAUTHORIZED)
+    }
+}
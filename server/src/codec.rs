@@ -0,0 +1,154 @@
+//! Payload formats accepted by `/p`.
+//!
+//! Version 1 is a msgpack map of key to an array of float32.
+//!
+//! Version 2 (`/p?<payload>&v=2`) is a msgpack map of key to
+//! `[format, scale, offset, start, data]` where `data` is a msgpack bin of
+//! big-endian samples. The low nibble of `format` selects the sample width:
+//!
+//! - `0`: float32, `scale`, `offset` and `start` are ignored
+//! - `1`: int8
+//! - `2`: int16
+//!
+//! Integer samples decode to `offset + scale * q`. With the delta flag
+//! (`0x10`) `start` is the first `q` and every sample in `data` is the
+//! difference to the previous tick.
+use serde::Deserialize;
+use std::collections::HashMap;
+
+const FORMAT_F32: u8 = 0;
+const FORMAT_I8: u8 = 1;
+const FORMAT_I16: u8 = 2;
+const FLAG_DELTA: u8 = 0x10;
+
+#[derive(Deserialize)]
+struct Channel(u8, f32, f32, i32, serde_bytes::ByteBuf);
+
+pub fn decode(version: u32, data: &[u8]) -> Result<HashMap<String, Vec<f32>>, String> {
+    match version {
+        1 => {
+            rmp_serde::from_slice(data).map_err(|e| format!("failed to decode message pack: {}", e))
+        }
+        2 => {
+            let channels = rmp_serde::from_slice::<HashMap<String, Channel>>(data)
+                .map_err(|e| format!("failed to decode message pack: {}", e))?;
+            channels
+                .into_iter()
+                .map(|(k, c)| {
+                    let v = decode_channel(&c).map_err(|e| format!("{}: {}", k, e))?;
+                    Ok((k, v))
+                })
+                .collect()
+        }
+        v => Err(format!("unsupported payload version: {}", v)),
+    }
+}
+
+fn decode_channel(
+    &Channel(format, scale, offset, start, ref data): &Channel,
+) -> Result<Vec<f32>, String> {
+    let q: Vec<i32> = match format & 0x0f {
+        FORMAT_F32 if format & FLAG_DELTA == 0 => {
+            return Ok(samples::<4>(data)?.map(f32::from_be_bytes).collect());
+        }
+        FORMAT_I8 => samples::<1>(data)?
+            .map(|b| i8::from_be_bytes(b) as i32)
+            .collect(),
+        FORMAT_I16 => samples::<2>(data)?
+            .map(|b| i16::from_be_bytes(b) as i32)
+            .collect(),
+        _ => return Err(format!("unsupported format: {:#x}", format)),
+    };
+    let q = if format & FLAG_DELTA != 0 {
+        std::iter::once(start)
+            .chain(q)
+            .scan(0i32, |acc, d| {
+                *acc = acc.wrapping_add(d);
+                Some(*acc)
+            })
+            .collect()
+    } else {
+        q
+    };
+    Ok(q.into_iter().map(|q| offset + scale * q as f32).collect())
+}
+
+fn samples<const N: usize>(data: &[u8]) -> Result<impl Iterator<Item = [u8; N]> + '_, String> {
+    if !data.len().is_multiple_of(N) {
+        return Err(format!(
+            "data length {} is not a multiple of {}",
+            data.len(),
+            N
+        ));
+    }
+    Ok(data.chunks_exact(N).map(|c| c.try_into().unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_bytes::ByteBuf;
+
+    fn v2(format: u8, scale: f32, offset: f32, start: i32, data: &[u8]) -> Vec<u8> {
+        let channel = (format, scale, offset, start, ByteBuf::from(data));
+        rmp_serde::to_vec(&HashMap::from([("k", channel)])).unwrap()
+    }
+
+    #[test]
+    fn v1() {
+        let values = HashMap::from([("k".to_owned(), vec![1.5, -2.0])]);
+        let payload = rmp_serde::to_vec(&values).unwrap();
+        assert_eq!(decode(1, &payload).unwrap(), values);
+    }
+
+    #[test]
+    fn f32() {
+        let data: Vec<u8> = [1.5f32, -0.25]
+            .iter()
+            .flat_map(|v| v.to_be_bytes())
+            .collect();
+        // scale, offset and start don't apply
+        let decoded = decode(2, &v2(FORMAT_F32, 2.0, 1.0, 7, &data)).unwrap();
+        assert_eq!(decoded["k"], [1.5, -0.25]);
+    }
+
+    #[test]
+    fn int16() {
+        let data: Vec<u8> = [300i16, -2].iter().flat_map(|v| v.to_be_bytes()).collect();
+        let decoded = decode(2, &v2(FORMAT_I16, 0.5, 10.0, 0, &data)).unwrap();
+        assert_eq!(decoded["k"], [160.0, 9.0]);
+    }
+
+    #[test]
+    fn int8_delta() {
+        let data: Vec<u8> = [1i8, -3, 127]
+            .iter()
+            .flat_map(|v| v.to_be_bytes())
+            .collect();
+        let decoded = decode(2, &v2(FORMAT_I8 | FLAG_DELTA, 2.0, 1.0, 100, &data)).unwrap();
+        assert_eq!(decoded["k"], [201.0, 203.0, 197.0, 451.0]);
+    }
+
+    #[test]
+    fn wrong_length() {
+        let e = decode(2, &v2(FORMAT_I16, 1.0, 0.0, 0, &[0, 1, 2])).unwrap_err();
+        assert_eq!(e, "k: data length 3 is not a multiple of 2");
+    }
+
+    #[test]
+    fn unknown_format() {
+        let e = decode(2, &v2(3, 1.0, 0.0, 0, &[])).unwrap_err();
+        assert_eq!(e, "k: unsupported format: 0x3");
+        // float32 has no delta encoding
+        let e = decode(2, &v2(FORMAT_F32 | FLAG_DELTA, 1.0, 0.0, 0, &[])).unwrap_err();
+        assert_eq!(e, "k: unsupported format: 0x10");
+    }
+
+    #[test]
+    fn unknown_version() {
+        assert_eq!(
+            decode(3, &[]).unwrap_err(),
+            "unsupported payload version: 3"
+        );
+    }
+}
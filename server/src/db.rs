@@ -0,0 +1,665 @@
+//! Optional SQLite storage of sessions, keys, samples and annotations.
+//!
+//! Pushes are queued to a writer thread which commits everything queued
+//! in one transaction. Readers open their own read-only connection.
+use crate::{
+    rollup::{History, Resolution},
+    table::Table,
+    values::{Buffer, Snapshot},
+};
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        mpsc,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::oneshot;
+
+const SCHEMA: &str = "
+PRAGMA journal_mode = WAL;
+PRAGMA synchronous = NORMAL;
+CREATE TABLE IF NOT EXISTS sessions (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL,
+    -- unix time in seconds
+    started INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS keys (
+    id INTEGER PRIMARY KEY,
+    session INTEGER NOT NULL REFERENCES sessions(id),
+    name TEXT NOT NULL,
+    UNIQUE (session, name)
+);
+CREATE TABLE IF NOT EXISTS samples (
+    key INTEGER NOT NULL REFERENCES keys(id),
+    tick INTEGER NOT NULL,
+    -- NULL for lost samples
+    value REAL,
+    PRIMARY KEY (key, tick)
+) WITHOUT ROWID;
+CREATE TABLE IF NOT EXISTS annotations (
+    id INTEGER PRIMARY KEY,
+    session INTEGER NOT NULL REFERENCES sessions(id),
+    tick INTEGER NOT NULL,
+    text TEXT NOT NULL
+);
+";
+
+enum Write {
+    Samples {
+        session: i64,
+        key: String,
+        tick: u64,
+        values: Vec<f32>,
+    },
+    Annotation {
+        session: i64,
+        tick: u64,
+        text: String,
+    },
+    /// Starts a new session, replying with its id once committed
+    Session {
+        reply: oneshot::Sender<Result<i64, String>>,
+    },
+}
+
+pub struct Db {
+    path: PathBuf,
+    session: AtomicI64,
+    tx: mpsc::Sender<Write>,
+}
+
+impl Db {
+    /// Opens or creates the database and starts a new session in it.
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let conn = open(path)?;
+        let session = new_session(&conn, "server")?;
+        log::info!("recording session {} to {}", session, path.display());
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || writer(conn, rx));
+        Ok(Self {
+            path: path.to_owned(),
+            session: AtomicI64::new(session),
+            tx,
+        })
+    }
+
+    /// Writes from now on go to a new session, whose id is returned.
+    ///
+    /// The session is created by the writer thread after the writes queued
+    /// before, which still go to the previous session.
+    pub async fn new_session(&self) -> Result<i64, String> {
+        let (reply, rx) = oneshot::channel();
+        self.tx
+            .send(Write::Session { reply })
+            .map_err(|_| "database writer stopped".to_owned())?;
+        let session = rx
+            .await
+            .map_err(|_| "database writer stopped".to_owned())??;
+        self.session.store(session, Ordering::Relaxed);
+        Ok(session)
+    }
+
+    pub fn session(&self) -> i64 {
+        self.session.load(Ordering::Relaxed)
+    }
+
+    /// Queues samples of `key` starting at `tick`
+    pub fn push(&self, key: &str, tick: u64, values: &[f32]) {
+        let write = Write::Samples {
+            session: self.session(),
+            key: key.to_owned(),
+            tick,
+            values: values.to_vec(),
+        };
+        if self.tx.send(write).is_err() {
+            log::error!("database writer stopped, dropping samples of {}", key);
+        }
+    }
+
+    pub fn annotate(&self, tick: u64, text: String) {
+        let write = Write::Annotation {
+            session: self.session(),
+            tick,
+            text,
+        };
+        if self.tx.send(write).is_err() {
+            log::error!("database writer stopped, dropping annotation");
+        }
+    }
+
+    /// Read-only connection, e.g. for `/query`
+    pub fn reader(&self) -> Result<Connection, String> {
+        let conn = Connection::open_with_flags(
+            &self.path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )
+        .map_err(|e| e.to_string())?;
+        conn.execute_batch("PRAGMA query_only = ON;")
+            .map_err(|e| e.to_string())?;
+        Ok(conn)
+    }
+}
+
+fn open(path: &Path) -> Result<Connection, String> {
+    let conn = Connection::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    conn.execute_batch(SCHEMA).map_err(|e| e.to_string())?;
+    Ok(conn)
+}
+
+fn new_session(conn: &Connection, name: &str) -> Result<i64, String> {
+    insert_session(conn, name).map_err(|e| e.to_string())
+}
+
+fn insert_session(conn: &Connection, name: &str) -> rusqlite::Result<i64> {
+    let started = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    conn.execute(
+        "INSERT INTO sessions (name, started) VALUES (?1, ?2)",
+        params![name, started],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+fn key_id(
+    conn: &Connection,
+    ids: &mut HashMap<(i64, String), i64>,
+    session: i64,
+    key: &str,
+) -> rusqlite::Result<i64> {
+    if let Some(id) = ids.get(&(session, key.to_owned())) {
+        return Ok(*id);
+    }
+    conn.execute(
+        "INSERT OR IGNORE INTO keys (session, name) VALUES (?1, ?2)",
+        params![session, key],
+    )?;
+    let id = conn.query_row(
+        "SELECT id FROM keys WHERE session = ?1 AND name = ?2",
+        params![session, key],
+        |row| row.get(0),
+    )?;
+    ids.insert((session, key.to_owned()), id);
+    Ok(id)
+}
+
+fn insert_samples(
+    conn: &Connection,
+    ids: &mut HashMap<(i64, String), i64>,
+    session: i64,
+    key: &str,
+    tick: u64,
+    values: &[f32],
+) -> rusqlite::Result<()> {
+    let id = key_id(conn, ids, session, key)?;
+    let mut insert = conn
+        .prepare_cached("INSERT OR REPLACE INTO samples (key, tick, value) VALUES (?1, ?2, ?3)")?;
+    for (i, v) in values.iter().enumerate() {
+        let v = if v.is_nan() { None } else { Some(*v) };
+        insert.execute(params![id, tick + i as u64, v])?;
+    }
+    Ok(())
+}
+
+fn writer(mut conn: Connection, rx: mpsc::Receiver<Write>) {
+    let mut ids = HashMap::new();
+    while let Ok(first) = rx.recv() {
+        let mut replies = vec![];
+        let result = conn.transaction().and_then(|tx| {
+            for write in std::iter::once(first).chain(rx.try_iter()) {
+                match write {
+                    Write::Samples {
+                        session,
+                        key,
+                        tick,
+                        values,
+                    } => insert_samples(&tx, &mut ids, session, &key, tick, &values)?,
+                    Write::Annotation {
+                        session,
+                        tick,
+                        text,
+                    } => {
+                        tx.execute(
+                            "INSERT INTO annotations (session, tick, text) VALUES (?1, ?2, ?3)",
+                            params![session, tick, text],
+                        )?;
+                    }
+                    Write::Session { reply } => {
+                        // replied to after the commit, failing with it
+                        let session = insert_session(&tx, "server");
+                        replies.push((*session.as_ref().unwrap_or(&0), reply));
+                        session?;
+                    }
+                }
+            }
+            tx.commit()
+        });
+        if let Err(e) = &result {
+            log::error!("failed to write to the database: {}", e);
+            ids.clear();
+        }
+        for (session, reply) in replies {
+            let session = result.as_ref().map(|()| session);
+            reply.send(session.map_err(|e| e.to_string())).ok();
+        }
+    }
+}
+
+/// Samples of a key in a session, rolled up in SQL
+pub fn history(
+    conn: &Connection,
+    session: i64,
+    key: &str,
+    resolution: Resolution,
+) -> Result<Option<History>, String> {
+    let step: u64 = match resolution {
+        Resolution::Raw => 1,
+        Resolution::Second => 60,
+        Resolution::Minute => 60 * 60,
+    };
+    let id: Option<i64> = conn
+        .query_row(
+            "SELECT id FROM keys WHERE session = ?1 AND name = ?2",
+            params![session, key],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?;
+    let Some(id) = id else {
+        return Ok(None);
+    };
+    let mut select = conn
+        .prepare(
+            "SELECT tick / ?2 AS bucket, min(value), max(value), avg(value) FROM samples
+             WHERE key = ?1 GROUP BY bucket ORDER BY bucket",
+        )
+        .map_err(|e| e.to_string())?;
+    let rows = select
+        .query_map(params![id, step], |row| {
+            Ok((
+                row.get::<_, u64>(0)?,
+                row.get::<_, Option<f64>>(1)?,
+                row.get::<_, Option<f64>>(2)?,
+                row.get::<_, Option<f64>>(3)?,
+            ))
+        })
+        .map_err(|e| e.to_string())?;
+
+    let mut history = History {
+        step,
+        ..Default::default()
+    };
+    let mut next = None;
+    for row in rows {
+        let (bucket, min, max, mean) = row.map_err(|e| e.to_string())?;
+        let next = next.get_or_insert_with(|| {
+            history.start = bucket * step;
+            bucket
+        });
+        // buckets without any row
+        while *next < bucket {
+            history.min.push(f32::NAN);
+            history.max.push(f32::NAN);
+            history.mean.push(f32::NAN);
+            *next += 1;
+        }
+        let f = |v: Option<f64>| v.map_or(f32::NAN, |v| v as f32);
+        history.min.push(f(min));
+        history.max.push(f(max));
+        history.mean.push(f(mean));
+        *next += 1;
+    }
+    Ok(Some(history))
+}
+
+#[derive(Debug, Serialize)]
+pub struct Rows {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<serde_json::Value>>,
+}
+
+/// Time a `/query` statement may run
+const QUERY_TIMEOUT: Duration = Duration::from_secs(10);
+/// Rows a `/query` statement may return
+const QUERY_ROWS: usize = 100_000;
+
+/// Runs a read-only statement, failing when it exceeds [`QUERY_TIMEOUT`] or
+/// [`QUERY_ROWS`]
+pub fn query(conn: &Connection, sql: &str) -> Result<Rows, String> {
+    let deadline = Instant::now() + QUERY_TIMEOUT;
+    conn.progress_handler(10_000, Some(move || Instant::now() > deadline));
+    let rows = query_rows(conn, sql).map_err(|e| {
+        if Instant::now() > deadline {
+            format!("query took longer than {:?}", QUERY_TIMEOUT)
+        } else {
+            e
+        }
+    });
+    conn.progress_handler(0, None::<fn() -> bool>);
+    rows
+}
+
+fn query_rows(conn: &Connection, sql: &str) -> Result<Rows, String> {
+    use rusqlite::types::ValueRef;
+    let mut statement = conn.prepare(sql).map_err(|e| e.to_string())?;
+    let columns: Vec<String> = statement
+        .column_names()
+        .into_iter()
+        .map(|c| c.to_owned())
+        .collect();
+    let mut rows = statement.query([]).map_err(|e| e.to_string())?;
+    let mut result = Vec::new();
+    while let Some(row) = rows.next().map_err(|e| e.to_string())? {
+        if result.len() == QUERY_ROWS {
+            return Err(format!("more than {} rows, add a LIMIT", QUERY_ROWS));
+        }
+        let values = (0..columns.len())
+            .map(|i| match row.get_ref(i) {
+                Ok(ValueRef::Null) => serde_json::Value::Null,
+                Ok(ValueRef::Integer(v)) => v.into(),
+                Ok(ValueRef::Real(v)) => v.into(),
+                Ok(ValueRef::Text(v)) => String::from_utf8_lossy(v).into(),
+                Ok(ValueRef::Blob(v)) => v.into(),
+                Err(e) => e.to_string().into(),
+            })
+            .collect();
+        result.push(values);
+    }
+    Ok(Rows {
+        columns,
+        rows: result,
+    })
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct ExportOptions {
+    /// SQLite database written with --db
+    pub db: PathBuf,
+    /// Session to export, the latest by default
+    #[arg(short, long)]
+    pub session: Option<i64>,
+}
+
+/// `{"values": ...}` like `/download.json`
+pub fn export(options: &ExportOptions) -> Result<String, String> {
+    let (conn, session) = open_session(&options.db, options.session)?;
+    serde_json::to_string(&snapshot(&conn, session)?).map_err(|e| e.to_string())
+}
+
+/// Opens a database read-only with `session` or its latest one
+pub fn open_session(path: &Path, session: Option<i64>) -> Result<(Connection, i64), String> {
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    let session = match session {
+        Some(session) => session,
+        None => conn
+            .query_row("SELECT max(id) FROM sessions", [], |row| {
+                row.get::<_, Option<i64>>(0)
+            })
+            .map_err(|e| e.to_string())?
+            .ok_or("no sessions in the database")?,
+    };
+    Ok((conn, session))
+}
+
+/// Samples of a session, keys padded with NaN to end together
+pub fn snapshot(conn: &Connection, session: i64) -> Result<Snapshot, String> {
+    let buffers = buffers(conn, session)?;
+    let end = buffers
+        .values()
+        .map(|b| b.start + b.values.len() as u64)
+        .max()
+        .unwrap_or_default();
+    let mut snapshot = Snapshot::default();
+    for (key, buffer) in buffers {
+        let mut values = buffer.values;
+        values.resize((end - buffer.start) as usize, f32::NAN);
+        snapshot.values.insert(key, values);
+    }
+    Ok(snapshot)
+}
+
+/// Samples of each key of a session from its first to its last tick, gaps
+/// filled with NaN
+pub fn buffers(conn: &Connection, session: i64) -> Result<HashMap<String, Buffer>, String> {
+    let mut select = conn
+        .prepare(
+            "SELECT keys.name, samples.tick, samples.value FROM samples
+             JOIN keys ON keys.id = samples.key WHERE keys.session = ?1
+             ORDER BY samples.key, samples.tick",
+        )
+        .map_err(|e| e.to_string())?;
+    let rows = select
+        .query_map([session], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, u64>(1)?,
+                row.get::<_, Option<f64>>(2)?,
+            ))
+        })
+        .map_err(|e| e.to_string())?;
+    let mut buffers = HashMap::<String, Buffer>::new();
+    for row in rows {
+        let (key, tick, value) = row.map_err(|e| e.to_string())?;
+        let buffer = buffers.entry(key).or_insert_with(|| Buffer {
+            start: tick,
+            values: vec![],
+        });
+        buffer
+            .values
+            .resize((tick - buffer.start) as usize, f32::NAN);
+        buffer.values.push(value.map_or(f32::NAN, |v| v as f32));
+    }
+    Ok(buffers)
+}
+
+/// Reads `keys` or all keys of a session from tick `from` up to `to`,
+/// merging a cursor per key by tick, and passes them on as tables of
+/// `block` ticks, so only one block is held at a time. `f` is called at least once.
+pub fn tables(
+    conn: &Connection,
+    session: i64,
+    keys: Option<Vec<String>>,
+    (from, to): (Option<u64>, Option<u64>),
+    block: u64,
+    mut f: impl FnMut(Table) -> Result<(), String>,
+) -> Result<(), String> {
+    let started = started(conn, session)?;
+    let mut select = conn
+        .prepare("SELECT id, name FROM keys WHERE session = ?1 ORDER BY name")
+        .map_err(|e| e.to_string())?;
+    let mut ids: HashMap<String, i64> = select
+        .query_map([session], |row| Ok((row.get(1)?, row.get(0)?)))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<_, _>>()
+        .map_err(|e| e.to_string())?;
+    let keys = keys.unwrap_or_else(|| {
+        let mut keys: Vec<_> = ids.keys().cloned().collect();
+        keys.sort_unstable();
+        keys
+    });
+    // a key given twice only gets its column once
+    let (keys, ids): (Vec<_>, Vec<_>) = keys
+        .into_iter()
+        .filter_map(|k| ids.remove(&k).map(|id| (k, id)))
+        .unzip();
+    // a cursor per key walks the primary key in tick order, merged by tick
+    let mut selects = ids
+        .iter()
+        .map(|_| {
+            conn.prepare(
+                "SELECT tick, value FROM samples
+                 WHERE key = ?1 AND tick >= ?2 AND tick < ?3 ORDER BY tick",
+            )
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    let to = to.map_or(i64::MAX, |to| to.min(i64::MAX as u64) as i64);
+    let mut cursors = selects
+        .iter_mut()
+        .zip(&ids)
+        .map(|(select, id)| select.query(params![id, from.unwrap_or_default(), to]))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    let next = |rows: &mut rusqlite::Rows| -> rusqlite::Result<Option<(u64, Option<f64>)>> {
+        rows.next()?
+            .map(|row| Ok((row.get(0)?, row.get(1)?)))
+            .transpose()
+    };
+    let mut heads = BinaryHeap::new();
+    let mut values = vec![None; cursors.len()];
+    for (column, rows) in cursors.iter_mut().enumerate() {
+        if let Some((tick, value)) = next(rows).map_err(|e| e.to_string())? {
+            values[column] = value;
+            heads.push(Reverse((tick, column)));
+        }
+    }
+
+    let empty = |start| -> Vec<Buffer> {
+        (0..keys.len())
+            .map(|_| Buffer {
+                start,
+                values: vec![],
+            })
+            .collect()
+    };
+    let mut start = None;
+    let mut buffers = vec![];
+    let mut end = 0;
+    while let Some(Reverse((tick, column))) = heads.pop() {
+        let value = values[column];
+        let start = start.get_or_insert_with(|| {
+            buffers = empty(tick);
+            tick
+        });
+        // ticks without any sample still get their rows
+        while tick >= *start + block {
+            let full = std::mem::replace(&mut buffers, empty(*start + block));
+            f(Table::block(
+                keys.clone(),
+                full,
+                started,
+                *start..*start + block,
+            ))?;
+            *start += block;
+        }
+        let buffer = &mut buffers[column];
+        buffer
+            .values
+            .resize((tick - buffer.start) as usize, f32::NAN);
+        buffer.values.push(value.map_or(f32::NAN, |v| v as f32));
+        end = tick + 1;
+        if let Some((tick, value)) = next(&mut cursors[column]).map_err(|e| e.to_string())? {
+            values[column] = value;
+            heads.push(Reverse((tick, column)));
+        }
+    }
+    let start = start.unwrap_or_default();
+    if buffers.is_empty() {
+        buffers = empty(start);
+    }
+    f(Table::block(keys, buffers, started, start..end.max(start)))
+}
+
+/// Unix time in seconds of the start of a session
+pub fn started(conn: &Connection, session: i64) -> Result<u64, String> {
+    conn.query_row(
+        "SELECT started FROM sessions WHERE id = ?1",
+        [session],
+        |row| row.get(0),
+    )
+    .optional()
+    .map_err(|e| e.to_string())?
+    .ok_or_else(|| format!("unknown session {}", session))
+}
+
+/// Id and start in unix seconds of all sessions
+pub fn sessions(conn: &Connection) -> Result<Vec<(i64, u64)>, String> {
+    let mut select = conn
+        .prepare("SELECT id, started FROM sessions ORDER BY id")
+        .map_err(|e| e.to_string())?;
+    let rows = select
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+        .map_err(|e| e.to_string())?;
+    rows.collect::<Result<_, _>>().map_err(|e| e.to_string())
+}
+
+/// Names of the keys of all sessions
+pub fn key_names(conn: &Connection) -> Result<Vec<String>, String> {
+    let mut select = conn
+        .prepare("SELECT DISTINCT name FROM keys")
+        .map_err(|e| e.to_string())?;
+    let rows = select
+        .query_map([], |row| row.get(0))
+        .map_err(|e| e.to_string())?;
+    rows.collect::<Result<_, _>>().map_err(|e| e.to_string())
+}
+
+/// Session, tick and text of all annotations
+pub fn annotations(conn: &Connection) -> Result<Vec<(i64, u64, String)>, String> {
+    let mut select = conn
+        .prepare("SELECT session, tick, text FROM annotations ORDER BY id")
+        .map_err(|e| e.to_string())?;
+    let rows = select
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+        .map_err(|e| e.to_string())?;
+    rows.collect::<Result<_, _>>().map_err(|e| e.to_string())
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct ImportOptions {
+    /// SQLite database, created when missing
+    pub db: PathBuf,
+    /// Dump of `/download.json` or `export`
+    pub dump: PathBuf,
+    /// Name of the new session, the file name by default
+    #[arg(short, long)]
+    pub name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Dump {
+    values: HashMap<String, Vec<Option<f32>>>,
+}
+
+/// Stores a dump as a new session, keys aligned at their last sample
+pub fn import(options: &ImportOptions) -> Result<String, String> {
+    let file = std::fs::File::open(&options.dump)
+        .map_err(|e| format!("{}: {}", options.dump.display(), e))?;
+    let dump: Dump = serde_json::from_reader(std::io::BufReader::new(file))
+        .map_err(|e| format!("{}: {}", options.dump.display(), e))?;
+    let name = options
+        .name
+        .clone()
+        .unwrap_or_else(|| options.dump.display().to_string());
+
+    let mut conn = open(&options.db)?;
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    let session = new_session(&tx, &name)?;
+    let end = dump
+        .values
+        .values()
+        .map(|v| v.len())
+        .max()
+        .unwrap_or_default();
+    let mut ids = HashMap::new();
+    for (key, values) in &dump.values {
+        let values: Vec<f32> = values.iter().map(|v| v.unwrap_or(f32::NAN)).collect();
+        let tick = (end - values.len()) as u64;
+        insert_samples(&tx, &mut ids, session, key, tick, &values).map_err(|e| e.to_string())?;
+    }
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(format!(
+        "imported {} keys as session {}\n",
+        dump.values.len(),
+        session
+    ))
+}
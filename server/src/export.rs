@@ -0,0 +1,200 @@
+//! Apache Parquet export with a tick, a timestamp and a nullable float
+//! column per key. Keys named like another column get a `_` prefix.
+use crate::{db, table::Table};
+use parquet::{
+    basic::{LogicalType, Repetition, TimeUnit, Type as PhysicalType},
+    data_type::{FloatType, Int64Type},
+    file::{properties::WriterProperties, writer::SerializedFileWriter},
+    format::MilliSeconds,
+    schema::types::Type,
+};
+use std::{collections::HashSet, io::Write, path::PathBuf, sync::Arc};
+
+pub const ROWS_PER_GROUP: u64 = 1 << 16;
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct Options {
+    /// SQLite database written with --db
+    pub db: PathBuf,
+    /// Parquet file to write
+    pub output: PathBuf,
+    /// Session to export, the latest by default
+    #[arg(short, long)]
+    pub session: Option<i64>,
+    /// Keys to export, all by default
+    #[arg(short, long, value_delimiter = ',')]
+    pub keys: Option<Vec<String>>,
+    /// First tick to export
+    #[arg(long)]
+    pub from: Option<u64>,
+    /// Tick after the last one to export
+    #[arg(long)]
+    pub to: Option<u64>,
+}
+
+pub fn run(options: &Options) -> Result<String, String> {
+    let (conn, session) = db::open_session(&options.db, options.session)?;
+    // the schema needs the keys of the first table, and nothing is written
+    // before the first row
+    let mut writer = None;
+    let mut ticks = 0;
+    db::tables(
+        &conn,
+        session,
+        options.keys.clone(),
+        (options.from, options.to),
+        ROWS_PER_GROUP,
+        |table| {
+            if table.ticks().is_empty() {
+                return Ok(());
+            }
+            ticks += table.ticks().count();
+            let writer = match &mut writer {
+                Some(writer) => writer,
+                None => {
+                    let file = std::fs::File::create(&options.output)
+                        .map_err(|e| format!("{}: {}", options.output.display(), e))?;
+                    writer.insert(Writer::new(table.keys(), std::io::BufWriter::new(file))?)
+                }
+            };
+            writer.write(&table)
+        },
+    )?;
+    writer
+        .ok_or("nothing to export")?
+        .close()?
+        .flush()
+        .map_err(|e| e.to_string())?;
+    log::info!(
+        "exported {} ticks of session {} to {}",
+        ticks,
+        session,
+        options.output.display()
+    );
+    Ok(String::new())
+}
+
+/// Column names of `keys`, prefixed with `_` until they differ from the
+/// tick and timestamp columns and the other keys
+fn columns(keys: &[String]) -> Vec<String> {
+    let mut taken: HashSet<String> = ["tick", "timestamp"].map(String::from).into();
+    taken.extend(keys.iter().cloned());
+    keys.iter()
+        .map(|key| {
+            if key != "tick" && key != "timestamp" {
+                return key.clone();
+            }
+            let mut column = format!("_{}", key);
+            while taken.contains(&column) {
+                column.insert(0, '_');
+            }
+            taken.insert(column.clone());
+            column
+        })
+        .collect()
+}
+
+/// Parquet file written a table at a time, with a row group per
+/// `ROWS_PER_GROUP` ticks
+pub struct Writer<W: Write + Send> {
+    inner: SerializedFileWriter<W>,
+}
+
+fn e(e: parquet::errors::ParquetError) -> String {
+    e.to_string()
+}
+
+impl<W: Write + Send> Writer<W> {
+    /// Writes the schema of a table of `keys`
+    pub fn new(keys: &[String], writer: W) -> Result<Self, String> {
+        let mut fields = vec![
+            Type::primitive_type_builder("tick", PhysicalType::INT64)
+                .with_repetition(Repetition::REQUIRED)
+                .build(),
+            Type::primitive_type_builder("timestamp", PhysicalType::INT64)
+                .with_repetition(Repetition::REQUIRED)
+                .with_logical_type(Some(LogicalType::Timestamp {
+                    is_adjusted_to_u_t_c: true,
+                    unit: TimeUnit::MILLIS(MilliSeconds {}),
+                }))
+                .build(),
+        ];
+        for key in columns(keys) {
+            fields.push(
+                Type::primitive_type_builder(&key, PhysicalType::FLOAT)
+                    .with_repetition(Repetition::OPTIONAL)
+                    .build(),
+            );
+        }
+        let fields = fields
+            .into_iter()
+            .map(|f| f.map(Arc::new))
+            .collect::<Result<_, _>>()
+            .map_err(e)?;
+        let schema = Type::group_type_builder("session")
+            .with_fields(fields)
+            .build()
+            .map_err(e)?;
+        let properties = WriterProperties::builder().build();
+        let inner =
+            SerializedFileWriter::new(writer, Arc::new(schema), Arc::new(properties)).map_err(e)?;
+        Ok(Self { inner })
+    }
+
+    /// Appends the rows of a table with the keys given to `new`
+    pub fn write(&mut self, table: &Table) -> Result<(), String> {
+        let ticks = table.ticks();
+        for first in ticks.clone().step_by(ROWS_PER_GROUP as usize) {
+            let group = first..(first + ROWS_PER_GROUP).min(ticks.end);
+            let mut row_group = self.inner.next_row_group().map_err(e)?;
+            let mut column = 0;
+            while let Some(mut writer) = row_group.next_column().map_err(e)? {
+                match column {
+                    0 => {
+                        let tick: Vec<_> = group.clone().map(|t| t as i64).collect();
+                        writer.typed::<Int64Type>().write_batch(&tick, None, None)
+                    }
+                    1 => {
+                        let timestamp: Vec<_> = group
+                            .clone()
+                            .map(|t| (table.time(t) * 1000.0) as i64)
+                            .collect();
+                        writer
+                            .typed::<Int64Type>()
+                            .write_batch(&timestamp, None, None)
+                    }
+                    _ => {
+                        let values: Vec<_> =
+                            group.clone().map(|t| table.value(column - 2, t)).collect();
+                        let levels: Vec<_> = values.iter().map(|v| v.is_some() as i16).collect();
+                        let values: Vec<_> = values.into_iter().flatten().collect();
+                        writer
+                            .typed::<FloatType>()
+                            .write_batch(&values, Some(&levels), None)
+                    }
+                }
+                .map_err(e)?;
+                writer.close().map_err(e)?;
+                column += 1;
+            }
+            row_group.close().map_err(e)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the footer and returns the underlying writer
+    pub fn close(self) -> Result<W, String> {
+        self.inner.into_inner().map_err(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn column_names() {
+        let keys = ["a", "tick", "_tick", "timestamp"].map(String::from);
+        assert_eq!(columns(&keys), ["a", "__tick", "_tick", "_timestamp"]);
+    }
+}
@@ -0,0 +1,81 @@
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+pub const MIN_BATCH: usize = 10;
+pub const MAX_BATCH: usize = 60;
+const TICKS_PER_SECOND: f64 = 60.0;
+
+#[derive(Debug, Default)]
+struct Link {
+    last_reply: Option<Instant>,
+    rtt: Option<Duration>,
+    batch: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LinkStats {
+    pub rtt_ms: Option<f64>,
+    pub batch: usize,
+}
+
+/// Round-trip latency per `src` of `/p` and the batch size recommended to it.
+///
+/// The round trip is the time between a reply and the next request, minus
+/// the `w` ticks the script reports to have waited after the reply.
+#[derive(Debug, Default)]
+pub struct Flow {
+    links: HashMap<String, Link>,
+}
+
+impl Flow {
+    pub fn request(&mut self, source: &str, waited_ticks: Option<u32>) {
+        let link = self.links.entry(source.to_owned()).or_default();
+        let (Some(last_reply), Some(waited)) = (link.last_reply, waited_ticks) else {
+            return;
+        };
+        let waited = Duration::from_secs_f64(waited as f64 / TICKS_PER_SECOND);
+        let sample = last_reply.elapsed().saturating_sub(waited);
+        link.rtt = Some(match link.rtt {
+            Some(rtt) => (rtt * 7 + sample) / 8,
+            None => sample,
+        });
+    }
+
+    /// Records the reply time and returns the batch size to announce.
+    pub fn reply(&mut self, source: &str, busy: bool, recording: bool) -> usize {
+        let link = self.links.entry(source.to_owned()).or_default();
+        link.last_reply = Some(Instant::now());
+        link.batch = if !recording {
+            MAX_BATCH
+        } else {
+            // fill a batch while the previous request is in flight
+            let ticks = link
+                .rtt
+                .map(|rtt| (rtt.as_secs_f64() * TICKS_PER_SECOND).ceil() as usize)
+                .unwrap_or(MIN_BATCH)
+                .clamp(MIN_BATCH, MAX_BATCH);
+            if busy {
+                (ticks * 2).min(MAX_BATCH)
+            } else {
+                ticks
+            }
+        };
+        link.batch
+    }
+
+    pub fn stats(&self) -> HashMap<String, LinkStats> {
+        self.links
+            .iter()
+            .map(|(k, l)| {
+                let stats = LinkStats {
+                    rtt_ms: l.rtt.map(|rtt| rtt.as_secs_f64() * 1000.0),
+                    batch: l.batch,
+                };
+                (k.clone(), stats)
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,200 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// Upper bound for the part count of a single message
+const MAX_PARTS: usize = 256;
+/// Incomplete messages kept, the least recently updated is dropped beyond
+const MAX_PENDING: usize = 64;
+
+pub enum Fragment {
+    Complete(Vec<u8>),
+    Incomplete { missing: Vec<usize> },
+}
+
+struct Pending {
+    parts: Vec<Option<Vec<u8>>>,
+    updated: Instant,
+}
+
+impl Pending {
+    fn missing(&self) -> Vec<usize> {
+        self.parts
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| p.is_none())
+            .map(|(i, _)| i)
+            .collect()
+    }
+}
+
+/// Buffers parts of messages split across multiple `/p` requests, by `src`
+/// and message id.
+pub struct Reassembler {
+    messages: HashMap<(String, u32), Pending>,
+    timeout: Duration,
+}
+
+impl Reassembler {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            messages: Default::default(),
+            timeout,
+        }
+    }
+
+    pub fn insert(
+        &mut self,
+        source: &str,
+        id: u32,
+        index: usize,
+        count: usize,
+        data: Vec<u8>,
+    ) -> Result<Fragment, String> {
+        self.expire();
+        if count == 0 || count > MAX_PARTS {
+            return Err(format!("invalid part count: {}", count));
+        }
+        if index >= count {
+            return Err(format!("part index {} out of range 0..{}", index, count));
+        }
+        let key = (source.to_owned(), id);
+        if !self.messages.contains_key(&key) && self.messages.len() >= MAX_PENDING {
+            self.drop_oldest();
+        }
+        let message = self.messages.entry(key.clone()).or_insert_with(|| Pending {
+            parts: vec![None; count],
+            updated: Instant::now(),
+        });
+        if message.parts.len() != count {
+            let expected = message.parts.len();
+            self.messages.remove(&key);
+            return Err(format!(
+                "part count mismatch for message {}: {} != {}",
+                id, count, expected
+            ));
+        }
+        message.parts[index] = Some(data);
+        message.updated = Instant::now();
+
+        let missing = message.missing();
+        if !missing.is_empty() {
+            return Ok(Fragment::Incomplete { missing });
+        }
+        let message = self.messages.remove(&key).unwrap();
+        Ok(Fragment::Complete(
+            message.parts.into_iter().flatten().flatten().collect(),
+        ))
+    }
+
+    fn expire(&mut self) {
+        let timeout = self.timeout;
+        self.messages.retain(|(source, id), m| {
+            let alive = m.updated.elapsed() < timeout;
+            if !alive {
+                log::warn!(
+                    "message {} from {:?} timed out, missing parts {:?}",
+                    id,
+                    source,
+                    m.missing()
+                );
+            }
+            alive
+        });
+    }
+
+    fn drop_oldest(&mut self) {
+        let oldest = self
+            .messages
+            .iter()
+            .min_by_key(|(_, m)| m.updated)
+            .map(|(key, _)| key.clone());
+        if let Some((source, id)) = oldest {
+            log::warn!(
+                "too many incomplete messages, dropping {} from {:?}",
+                id,
+                source
+            );
+            self.messages.remove(&(source, id));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn complete(fragment: Result<Fragment, String>) -> Vec<u8> {
+        match fragment.unwrap() {
+            Fragment::Complete(data) => data,
+            Fragment::Incomplete { missing } => panic!("missing {:?}", missing),
+        }
+    }
+
+    fn missing(fragment: Result<Fragment, String>) -> Vec<usize> {
+        match fragment.unwrap() {
+            Fragment::Complete(data) => panic!("complete {:?}", data),
+            Fragment::Incomplete { missing } => missing,
+        }
+    }
+
+    #[test]
+    fn out_of_order() {
+        let mut reassembler = Reassembler::new(Duration::from_secs(5));
+        assert_eq!(missing(reassembler.insert("a", 1, 2, 3, vec![3])), [0, 1]);
+        assert_eq!(missing(reassembler.insert("a", 1, 0, 3, vec![1])), [1]);
+        assert_eq!(
+            complete(reassembler.insert("a", 1, 1, 3, vec![2])),
+            [1, 2, 3]
+        );
+    }
+
+    #[test]
+    fn sources() {
+        let mut reassembler = Reassembler::new(Duration::from_secs(5));
+        missing(reassembler.insert("a", 1, 0, 2, vec![1]));
+        // the same id from another source is another message
+        missing(reassembler.insert("b", 1, 0, 2, vec![3]));
+        assert_eq!(complete(reassembler.insert("b", 1, 1, 2, vec![4])), [3, 4]);
+        assert_eq!(complete(reassembler.insert("a", 1, 1, 2, vec![2])), [1, 2]);
+    }
+
+    #[test]
+    fn timeout() {
+        let mut reassembler = Reassembler::new(Duration::from_millis(10));
+        missing(reassembler.insert("a", 1, 0, 2, vec![1]));
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(missing(reassembler.insert("a", 1, 1, 2, vec![2])), [0]);
+    }
+
+    #[test]
+    fn pending_limit() {
+        let mut reassembler = Reassembler::new(Duration::from_secs(5));
+        missing(reassembler.insert("a", 0, 0, 2, vec![1]));
+        std::thread::sleep(Duration::from_millis(1));
+        for id in 1..=MAX_PENDING as u32 {
+            missing(reassembler.insert("a", id, 0, 2, vec![1]));
+        }
+        assert_eq!(reassembler.messages.len(), MAX_PENDING);
+        // the first message was dropped for the last
+        assert_eq!(missing(reassembler.insert("a", 0, 1, 2, vec![2])), [0]);
+        let last = MAX_PENDING as u32;
+        assert_eq!(
+            complete(reassembler.insert("a", last, 1, 2, vec![2])),
+            [1, 2]
+        );
+    }
+
+    #[test]
+    fn invalid_parts() {
+        let mut reassembler = Reassembler::new(Duration::from_secs(5));
+        assert!(reassembler.insert("a", 1, 0, 0, vec![]).is_err());
+        assert!(reassembler.insert("a", 1, 2, 2, vec![]).is_err());
+        assert!(reassembler
+            .insert("a", 1, 0, MAX_PARTS + 1, vec![])
+            .is_err());
+        missing(reassembler.insert("a", 1, 0, 2, vec![1]));
+        assert!(reassembler.insert("a", 1, 1, 3, vec![2]).is_err());
+    }
+}
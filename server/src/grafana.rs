@@ -0,0 +1,355 @@
+//! Grafana SimpleJSON datasource at `/grafana`, ticks are mapped to time
+//! from the start of their session at 60 ticks per second.
+use crate::{
+    db,
+    rollup::{History, Resolution},
+    session_history, with_db, AppState,
+};
+use axum::{
+    extract::State,
+    http::StatusCode,
+    routing::{get, post},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const MS_PER_TICK: f64 = 1000.0 / 60.0;
+const DEFAULT_MAX_DATA_POINTS: usize = 1000;
+
+pub fn router() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/", get(|| async { "OK" }))
+        .route("/search", post(search))
+        .route("/query", post(query))
+        .route("/annotations", post(annotations))
+}
+
+#[derive(Deserialize)]
+struct Range {
+    from: String,
+    to: String,
+}
+
+impl Range {
+    /// Unix times in milliseconds
+    fn millis(&self) -> Result<(u64, u64), (StatusCode, String)> {
+        let from = parse_time(&self.from).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+        let to = parse_time(&self.to).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+        Ok((from, to.max(from)))
+    }
+}
+
+#[derive(Deserialize)]
+struct SearchRequest {
+    #[serde(default)]
+    target: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct QueryRequest {
+    range: Range,
+    targets: Vec<Target>,
+    max_data_points: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct Target {
+    #[serde(default)]
+    target: String,
+}
+
+#[derive(Serialize)]
+struct Series {
+    target: String,
+    /// value and unix time in milliseconds
+    datapoints: Vec<(Option<f32>, u64)>,
+}
+
+#[derive(Deserialize)]
+struct AnnotationRequest {
+    range: Range,
+    annotation: serde_json::Value,
+}
+
+#[derive(Serialize)]
+struct Annotation {
+    annotation: serde_json::Value,
+    time: u64,
+    title: String,
+    text: String,
+    tags: Vec<&'static str>,
+}
+
+/// Session whose data can be read, with the unix times in milliseconds of
+/// its tick 0 and of the start of the next session
+struct Span {
+    id: i64,
+    /// `None` for the current session
+    session: Option<i64>,
+    start: u64,
+    end: u64,
+}
+
+impl Span {
+    fn time(&self, tick: u64) -> u64 {
+        self.start + (tick as f64 * MS_PER_TICK) as u64
+    }
+}
+
+/// Key names of all sessions containing `target`
+async fn search(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<SearchRequest>,
+) -> Result<Json<BTreeSet<String>>, (StatusCode, String)> {
+    let mut keys: BTreeSet<String> = state.values.keys().into_iter().map(|k| k.name).collect();
+    for session in state.sessions.lock().await.list() {
+        keys.extend(session.keys);
+    }
+    if state.db.is_some() {
+        keys.extend(with_db(state.clone(), db::key_names).await?);
+    }
+    keys.retain(|k| k.contains(&request.target));
+    Ok(Json(keys))
+}
+
+/// Means of the targets in the range, from the finest history of each
+/// session giving at most about `maxDataPoints`
+async fn query(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<QueryRequest>,
+) -> Result<Json<Vec<Series>>, (StatusCode, String)> {
+    let (from, to) = request.range.millis()?;
+    let max_points = request
+        .max_data_points
+        .unwrap_or(DEFAULT_MAX_DATA_POINTS)
+        .max(1);
+    let interval = (to - from) / max_points as u64;
+    let resolution = if interval < 1000 {
+        Resolution::Raw
+    } else if interval < 60 * 1000 {
+        Resolution::Second
+    } else {
+        Resolution::Minute
+    };
+    let spans = spans(&state).await?;
+    let mut series = vec![];
+    for target in request.targets.iter().filter(|t| !t.target.is_empty()) {
+        let mut datapoints = vec![];
+        for span in spans.iter().filter(|s| s.start <= to && s.end >= from) {
+            let history = covering_history(&state, span, &target.target, resolution, from).await?;
+            let Some(history) = history else {
+                continue;
+            };
+            for (i, mean) in history.mean.iter().enumerate() {
+                let time = span.time(history.start + i as u64 * history.step);
+                if (from..=to).contains(&time) {
+                    datapoints.push(((!mean.is_nan()).then_some(*mean), time));
+                }
+            }
+        }
+        let stride = datapoints.len().div_ceil(max_points);
+        if stride > 1 {
+            datapoints = datapoints.into_iter().step_by(stride).collect();
+        }
+        series.push(Series {
+            target: target.target.clone(),
+            datapoints,
+        });
+    }
+    Ok(Json(series))
+}
+
+/// History at `resolution` or a coarser one reaching back to `from`, the raw
+/// buffers of the server only hold the last minute
+async fn covering_history(
+    state: &Arc<AppState>,
+    span: &Span,
+    key: &str,
+    resolution: Resolution,
+    from: u64,
+) -> Result<Option<History>, (StatusCode, String)> {
+    let resolutions = [Resolution::Raw, Resolution::Second, Resolution::Minute];
+    let mut history = None;
+    for resolution in resolutions.into_iter().skip_while(|r| *r != resolution) {
+        history = session_history(state.clone(), span.session, key, resolution).await?;
+        let Some(h) = &history else {
+            break;
+        };
+        let step = (h.step as f64 * MS_PER_TICK) as u64;
+        if span.time(h.start) <= from.max(span.start) + step {
+            break;
+        }
+    }
+    Ok(history)
+}
+
+/// Session starts and the annotations of the database whose text contains
+/// the query of the annotation
+async fn annotations(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<AnnotationRequest>,
+) -> Result<Json<Vec<Annotation>>, (StatusCode, String)> {
+    let (from, to) = request.range.millis()?;
+    let filter = request
+        .annotation
+        .get("query")
+        .and_then(|q| q.as_str())
+        .unwrap_or_default()
+        .to_owned();
+    let spans = spans(&state).await?;
+    let mut events: Vec<_> = spans
+        .iter()
+        .map(|s| (s.start, format!("session {}", s.id), "session"))
+        .collect();
+    if state.db.is_some() {
+        for (session, tick, text) in with_db(state.clone(), db::annotations).await? {
+            if let Some(span) = spans.iter().find(|s| s.id == session) {
+                events.push((span.time(tick), text, "annotation"));
+            }
+        }
+    }
+    let annotations = events
+        .into_iter()
+        .filter(|(time, text, _)| (from..=to).contains(time) && text.contains(&filter))
+        .map(|(time, text, tag)| Annotation {
+            annotation: request.annotation.clone(),
+            time,
+            title: text.clone(),
+            text,
+            tags: vec![tag],
+        })
+        .collect();
+    Ok(Json(annotations))
+}
+
+/// Current session, past ones in memory and the sessions of the database
+async fn spans(state: &Arc<AppState>) -> Result<Vec<Span>, (StatusCode, String)> {
+    let mut started = BTreeMap::new();
+    if state.db.is_some() {
+        for (id, start) in with_db(state.clone(), db::sessions).await? {
+            started.insert(id, start * 1000);
+        }
+    }
+    let (list, current) = {
+        let sessions = state.sessions.lock().await;
+        (sessions.list(), sessions.current())
+    };
+    for session in list {
+        // without a database only the archived buffers are left
+        let readable = session.archived || state.db.is_some();
+        match session.started {
+            // tick 0 is the first push
+            Some(start) if readable => {
+                started.insert(session.id, start * 1000);
+            }
+            _ => {
+                started.remove(&session.id);
+            }
+        }
+    }
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    let started: Vec<_> = started.into_iter().collect();
+    let spans = started
+        .iter()
+        .enumerate()
+        .map(|(i, &(id, start))| Span {
+            id,
+            session: (id != current).then_some(id),
+            start,
+            end: started.get(i + 1).map_or(now, |&(_, next)| next),
+        })
+        .collect();
+    Ok(spans)
+}
+
+/// Unix time in milliseconds of an RFC 3339 UTC time like
+/// `2024-01-31T12:00:00.000Z`, as sent by Grafana
+fn parse_time(s: &str) -> Result<u64, String> {
+    let invalid = || format!("expected a time like 2024-01-31T12:00:00.000Z: {}", s);
+    let (date, time) = s
+        .strip_suffix('Z')
+        .and_then(|s| s.split_once('T'))
+        .ok_or_else(invalid)?;
+    let (time, fraction) = time.split_once('.').unwrap_or((time, "0"));
+    let digits = |f: &str| !f.is_empty() && f.bytes().all(|b| b.is_ascii_digit());
+    if !digits(fraction) {
+        return Err(invalid());
+    }
+    let mut fields = date.split('-').chain(time.split(':')).map(|f| {
+        // 4 digits at most keeps the arithmetic below from overflowing
+        (digits(f) && f.len() <= 4)
+            .then(|| f.parse::<i64>().ok())
+            .flatten()
+    });
+    let mut field = |range: std::ops::RangeInclusive<i64>| {
+        fields
+            .next()
+            .flatten()
+            .filter(|f| range.contains(f))
+            .ok_or_else(invalid)
+    };
+    let (year, month, day) = (field(0..=9999)?, field(1..=12)?, field(1..=31)?);
+    let (hour, minute, second) = (field(0..=23)?, field(0..=59)?, field(0..=60)?);
+    if fields.next().is_some() {
+        return Err(invalid());
+    }
+    let millis: i64 = format!("{:0<3}", &fraction[..fraction.len().min(3)])
+        .parse()
+        .map_err(|_| invalid())?;
+
+    // days since 1970-01-01 of the proleptic Gregorian calendar, counting
+    // years from March so leap days come last
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+
+    let seconds = days * 86400 + hour * 3600 + minute * 60 + second;
+    u64::try_from(seconds * 1000 + millis).map_err(|_| invalid())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn times() {
+        assert_eq!(parse_time("1970-01-01T00:00:00Z"), Ok(0));
+        assert_eq!(parse_time("2024-01-31T12:00:00.000Z"), Ok(1706702400000));
+        assert_eq!(parse_time("2024-02-29T23:59:59.5Z"), Ok(1709251199500));
+        assert_eq!(parse_time("2024-02-29T23:59:59.123456Z"), Ok(1709251199123));
+    }
+
+    #[test]
+    fn invalid_times() {
+        for time in [
+            "",
+            "2024-01-31T12:00:00",
+            "2024-01-31 12:00:00Z",
+            "2024-01-31T12:00Z",
+            "2024-01-31T12:00:00:00Z",
+            "2024-13-01T00:00:00Z",
+            "2024-01-31T24:00:00Z",
+            "2024-01-31T12:00:+1Z",
+            "1969-12-31T23:59:59Z",
+            "2024-01-31T12:00:00.Z",
+            "2024-01-31T12:00:00.1éZ",
+            "2024-01-31T12:00:00.ééZ",
+            "99999999999999-01-31T12:00:00Z",
+            "2024-01-31T12:00:9999999999999999Z",
+        ] {
+            assert!(parse_time(time).is_err(), "{}", time);
+        }
+    }
+}
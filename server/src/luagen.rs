@@ -0,0 +1,431 @@
+//! Generator for minified microcontroller scripts pushing to `/p`.
+//!
+//! The scripts send the version 2 payload of [`crate::codec`] with
+//! fragmenting (`m`, `i`, `n`), sequence numbers (`s`) of a random run
+//! (`r`) and the flow control hints of [`crate::flow`].
+use std::{fmt::Write, str::FromStr};
+
+/// Maximum length of a Stormworks Lua script
+pub const MAX_SCRIPT_LEN: usize = 8192;
+/// Composite channels per type
+const MAX_INPUTS: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Number { scale: f32, offset: f32 },
+    Bool,
+}
+
+/// `name[:n[:scale[:offset]]]` or `name:b`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Channel {
+    pub name: String,
+    pub kind: Kind,
+}
+
+impl FromStr for Channel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut it = s.split(':');
+        let name = it.next().unwrap_or_default().to_owned();
+        if name.is_empty() || name.len() >= 32 {
+            return Err(format!("channel name must be 1 to 31 bytes: {:?}", name));
+        }
+        if !name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"_-.".contains(&b))
+        {
+            return Err(format!("unsupported character in channel name: {:?}", name));
+        }
+        let number = |s: Option<&str>| {
+            s.map(|s| s.parse::<f32>().map_err(|e| format!("{}: {}", s, e)))
+                .transpose()
+                .map(|v| v.unwrap_or_default())
+        };
+        let kind = match it.next() {
+            None | Some("n") => Kind::Number {
+                scale: number(it.next())?,
+                offset: number(it.next())?,
+            },
+            Some("b") => Kind::Bool,
+            Some(t) => return Err(format!("unknown channel type: {}", t)),
+        };
+        if it.next().is_some() {
+            return Err(format!("too many fields in channel: {}", s));
+        }
+        Ok(Channel { name, kind })
+    }
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct Options {
+    /// `name[:n[:scale[:offset]]]` for numbers (scale 0 sends float32) or `name:b` for booleans
+    #[arg(short, long = "channel", required = true)]
+    pub channels: Vec<Channel>,
+    /// Ticks per batch until the server recommends another size
+    #[arg(short, long, default_value = "10")]
+    pub batch: usize,
+    /// Port of the server
+    #[arg(short, long, default_value = "8080")]
+    pub port: u16,
+    /// Write token of the server
+    #[arg(short, long)]
+    pub token: Option<String>,
+    /// Only record while this composite boolean is on
+    #[arg(short, long)]
+    pub enable: Option<usize>,
+    /// Bytes per request before the payload is split
+    #[arg(long, default_value = "180")]
+    pub part_size: usize,
+    /// Read the key of channel N from the text property `label_N`, cut to
+    /// the 31 bytes of a msgpack fixstr
+    #[arg(skip)]
+    pub labels_from_properties: bool,
+}
+
+/// Composite input channel of every channel, numbers and booleans counted separately
+pub fn input_indices(options: &Options) -> Result<Vec<usize>, String> {
+    let mut number = 0;
+    let mut bool = 0;
+    let indices = options
+        .channels
+        .iter()
+        .map(|c| match c.kind {
+            Kind::Number { .. } => {
+                number += 1;
+                number
+            }
+            Kind::Bool => {
+                bool += 1;
+                if Some(bool) == options.enable {
+                    bool += 1;
+                }
+                bool
+            }
+        })
+        .collect::<Vec<_>>();
+    if number > MAX_INPUTS || bool > MAX_INPUTS {
+        return Err(format!("at most {} inputs per type", MAX_INPUTS));
+    }
+    Ok(indices)
+}
+
+pub fn generate(options: &Options) -> Result<String, String> {
+    if options.part_size == 0 || !options.part_size.is_multiple_of(3) {
+        return Err("part size must be a positive multiple of 3".into());
+    }
+    if options.enable.is_some_and(|e| e == 0 || e > MAX_INPUTS) {
+        return Err(format!("enable must be in 1..={}", MAX_INPUTS));
+    }
+    let indices = input_indices(options)?;
+    let list = |f: &dyn Fn(&Channel) -> String| {
+        options.channels.iter().map(f).collect::<Vec<_>>().join(",")
+    };
+    let keys = if options.labels_from_properties {
+        String::new()
+    } else {
+        list(&|c| format!("{:?}", c.name))
+    };
+    let bools = list(&|c| (c.kind == Kind::Bool).to_string());
+    let (scales, offsets) = (
+        list(&|c| match c.kind {
+            Kind::Number { scale, .. } => scale.to_string(),
+            Kind::Bool => "1".into(),
+        }),
+        list(&|c| match c.kind {
+            Kind::Number { offset, .. } => offset.to_string(),
+            Kind::Bool => "0".into(),
+        }),
+    );
+    let indices = indices
+        .iter()
+        .map(|i| i.to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+    let token = options
+        .token
+        .as_ref()
+        .map(|t| format!("&{}", serde_urlencoded::to_string([("token", t)]).unwrap()))
+        .unwrap_or_default();
+
+    let mut s = String::new();
+    writeln!(s, "K={{{}}}B={{{}}}I={{{}}}", keys, bools, indices).unwrap();
+    writeln!(s, "S={{{}}}O={{{}}}N=#B", scales, offsets).unwrap();
+    if options.labels_from_properties {
+        writeln!(
+            s,
+            r#"for i=1,N do K[i]=property.getText("label_"..i):sub(1,31)end"#
+        )
+        .unwrap();
+    }
+    writeln!(
+        s,
+        "P={} Q={:?} E={} L={} b={}",
+        options.port,
+        token,
+        options.enable.unwrap_or_default(),
+        options.part_size,
+        options.batch
+    )
+    .unwrap();
+    s.push_str(BODY);
+    if s.len() > MAX_SCRIPT_LEN {
+        return Err(format!(
+            "script is {} characters, exceeding {}",
+            s.len(),
+            MAX_SCRIPT_LEN
+        ));
+    }
+    Ok(s)
+}
+
+const BODY: &str = r#"C={}for i=0,63 do C[i]=("ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_"):sub(i+1,i+1)end
+V={}U={}n=0 w=0 p=0 q=0 m=0 G=math.random(1<<30)
+function onTick()w=w+1 local e=E==0 or input.getBool(E)for c,v in pairs(U)do output.setNumber(c,v)end
+if e then for i=1,N do V[i]=V[i]or{}local v if B[i]then v=input.getBool(I[i])and 1 or 0 else v=input.getNumber(I[i])end table.insert(V[i],v)end n=n+1 end
+if p>0 and w>300 then p=0 end
+if n==0 or p>0 or(e and n<b)then return end
+local t={N<16 and(">B"):pack(128+N)or(">BI2"):pack(222,N)}
+for i=1,N do local f,s,d=X(V[i],S[i],O[i])t[#t+1]=(">B"):pack(160+#K[i])..K[i]..(">BBBfBfBi4Bs2"):pack(149,f,202,S[i],202,O[i],210,s,197,table.concat(d))end
+Z(table.concat(t))V={}n=0 end
+function X(v,s,o)local d={}if s==0 then for _,x in ipairs(v)do d[#d+1]=(">f"):pack(x)end return 0,0,d end
+local r,z={},1 for i,x in ipairs(v)do r[i]=math.floor((x-o)/s+.5)if i>1 and math.abs(r[i]-r[i-1])>127 then z=0 end end
+if z>0 and #r>0 then for i=2,#r do d[#d+1]=(">i1"):pack(r[i]-r[i-1])end return 17,r[1],d end
+for _,x in ipairs(r)do d[#d+1]=(">i2"):pack(math.max(-32768,math.min(32767,x)))end return 2,0,d end
+function Z(s)local c=(#s+L-1)//L m=(m+1)%65536 q=(q+1)%65536
+for i=0,c-1 do local u="/p?"..Y(s:sub(i*L+1,(i+1)*L)).."&v=2&r="..G.."&s="..q.."&w="..w..Q
+if c>1 then u=u..("&m=%d&i=%d&n=%d"):format(m,i,c)end async.httpGet(P,u)p=p+1 end end
+function Y(s)local r=(s..("\0"):rep(2-(#s-1)%3)):gsub("...",function(x)local a,b,c=x:byte(1,3)return C[a>>2]..C[(a&3)<<4|b>>4]..C[(b&15)<<2|c>>6]..C[c&63]end)return r end
+function httpReply(_,_,r)p=math.max(p-1,0)if p==0 then w=0 end local x=r:match("^OK b=(%d+)")if x then b=tonumber(x)end
+local c,v=r:match(" c=(%d+)=(%S+)")if c then U[tonumber(c)]=tonumber(v)end end
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{codec, query::PushQuery};
+    use base64::prelude::*;
+    use std::collections::{BTreeMap, HashMap};
+
+    fn options(channels: &[&str]) -> Options {
+        Options {
+            channels: channels.iter().map(|c| c.parse().unwrap()).collect(),
+            batch: 10,
+            port: 8080,
+            token: None,
+            enable: None,
+            part_size: 180,
+            labels_from_properties: false,
+        }
+    }
+
+    /// Runs a script for `ticks` ticks, number input `i` reading `input(i, tick)`
+    /// and booleans being on at odd ticks, and decodes the batches it sends
+    /// like `/p` does
+    fn run(
+        script: &str,
+        ticks: usize,
+        input: fn(i64, usize) -> f32,
+        labels: &[&str],
+    ) -> Vec<HashMap<String, Vec<f32>>> {
+        run_with(script, ticks, input, |_, tick| tick % 2 == 1, labels, &[])
+    }
+
+    /// [`run`] with boolean input `i` reading `bools(i, tick)` and number
+    /// properties, `label_<n>` being `labels[n - 1]`
+    fn run_with(
+        script: &str,
+        ticks: usize,
+        input: fn(i64, usize) -> f32,
+        bools: fn(i64, usize) -> bool,
+        labels: &[&str],
+        numbers: &[(&str, f32)],
+    ) -> Vec<HashMap<String, Vec<f32>>> {
+        let lua = mlua::Lua::new();
+        lua.load(
+            r#"R={}T=0 input={getNumber=function(i)return NUMBER(i,T)end,getBool=function(i)return BOOL(i,T)end}
+            output={setNumber=function()end}async={httpGet=function(_,u)R[#R+1]=u end}"#,
+        )
+        .exec()
+        .unwrap();
+        let globals = lua.globals();
+        let number = lua
+            .create_function(move |_, (i, tick): (i64, usize)| Ok(input(i, tick)))
+            .unwrap();
+        globals.set("NUMBER", number).unwrap();
+        let bool = lua
+            .create_function(move |_, (i, tick): (i64, usize)| Ok(bools(i, tick)))
+            .unwrap();
+        globals.set("BOOL", bool).unwrap();
+        let labels: Vec<String> = labels.iter().map(|l| l.to_string()).collect();
+        let get_text = lua
+            .create_function(move |_, name: String| {
+                let label = name
+                    .strip_prefix("label_")
+                    .and_then(|i| i.parse::<usize>().ok())
+                    .and_then(|i| labels.get(i - 1));
+                Ok(label.cloned().unwrap_or_default())
+            })
+            .unwrap();
+        let numbers: HashMap<String, f32> =
+            numbers.iter().map(|(k, v)| (k.to_string(), *v)).collect();
+        let get_number = lua
+            .create_function(move |_, name: String| {
+                Ok(numbers.get(&name).copied().unwrap_or_default())
+            })
+            .unwrap();
+        let property = lua.create_table().unwrap();
+        property.set("getText", get_text).unwrap();
+        property.set("getNumber", get_number).unwrap();
+        globals.set("property", property).unwrap();
+        lua.load(script).exec().unwrap();
+
+        let mut urls = vec![];
+        for tick in 0..ticks {
+            globals.set("T", tick).unwrap();
+            lua.load("onTick()").exec().unwrap();
+            let sent: Vec<String> = lua.load("local r=R R={}return r").eval().unwrap();
+            for url in &sent {
+                let reply: mlua::Function = globals.get("httpReply").unwrap();
+                reply.call::<_, ()>((8080, url.as_str(), "OK")).unwrap();
+            }
+            urls.extend(sent);
+        }
+
+        let mut batches = vec![];
+        let mut fragments = BTreeMap::<u32, Vec<u8>>::new();
+        for url in &urls {
+            let query = PushQuery::parse(url.strip_prefix("/p?").unwrap());
+            let data = BASE64_URL_SAFE_NO_PAD.decode(query.payload).unwrap();
+            let data = match query.parse_param::<u32>("n").unwrap() {
+                None => data,
+                Some(count) => {
+                    let id = query.parse_param("m").unwrap().unwrap();
+                    let fragment = fragments.entry(id).or_default();
+                    fragment.extend(data);
+                    let index: u32 = query.parse_param("i").unwrap().unwrap();
+                    if index + 1 < count {
+                        continue;
+                    }
+                    fragments.remove(&id).unwrap()
+                }
+            };
+            let version = query.parse_param("v").unwrap().unwrap();
+            batches.push(codec::decode(version, &data).unwrap());
+        }
+        batches
+    }
+
+    #[test]
+    fn formats() {
+        let options = options(&["small:n:0.01", "big:n:1:-100", "float", "on:b"]);
+        let script = generate(&options).unwrap();
+        let input = |i, tick| match i {
+            // int8 deltas
+            1 => tick as f32 * 0.25,
+            // steps too large for int8
+            2 => (tick * 1000) as f32,
+            _ => tick as f32 / 3.0,
+        };
+        let batches = run(&script, 20, input, &[]);
+        assert_eq!(batches.len(), 2);
+        for (b, batch) in batches.iter().enumerate() {
+            let ticks = b * 10..(b + 1) * 10;
+            for (small, tick) in batch["small"].iter().zip(ticks.clone()) {
+                assert!(
+                    (small - input(1, tick)).abs() <= 0.005,
+                    "{} {}",
+                    small,
+                    tick
+                );
+            }
+            let big: Vec<_> = ticks.clone().map(|t| input(2, t)).collect();
+            assert_eq!(batch["big"], big);
+            let float: Vec<_> = ticks.clone().map(|t| input(3, t)).collect();
+            assert_eq!(batch["float"], float);
+            let on: Vec<_> = ticks.map(|t| (t % 2) as f32).collect();
+            assert_eq!(batch["on"], on);
+        }
+    }
+
+    #[test]
+    fn fragments() {
+        let mut options = options(&["a", "b", "c", "d:n:1", "e:b"]);
+        options.part_size = 30;
+        let script = generate(&options).unwrap();
+        let batches = run(&script, 10, |i, tick| (i as usize * tick) as f32, &[]);
+        assert_eq!(batches.len(), 1);
+        for (key, i) in [("a", 1), ("b", 2), ("c", 3), ("d", 4)] {
+            let values: Vec<_> = (0..10).map(|t| (i * t) as f32).collect();
+            assert_eq!(batches[0][key], values);
+        }
+    }
+
+    #[test]
+    fn labels_from_properties() {
+        let mut options = options(&["x", "y"]);
+        options.labels_from_properties = true;
+        let script = generate(&options).unwrap();
+        let long = "a_label_longer_than_a_fixstr_holds";
+        let batches = run(&script, 10, |_, _| 1.0, &["short", long]);
+        let mut keys: Vec<_> = batches[0].keys().cloned().collect();
+        keys.sort();
+        assert_eq!(keys, [&long[..31], "short"]);
+    }
+
+    #[test]
+    fn example_compact_script() {
+        let script = include_str!("../../example/metrics_compact.lua");
+        let input = |i, tick| match i {
+            1 => tick as f32 / 3.0,
+            // steps too large for int8
+            2 => (tick * 1000) as f32,
+            // int8 deltas
+            _ => tick as f32 * 0.25,
+        };
+        let batches = run_with(
+            script,
+            20,
+            input,
+            |_, _| true,
+            &["float", "int16", "delta"],
+            &[("scale_2", 1.0), ("offset_2", -100.0), ("scale_3", 0.01)],
+        );
+        assert_eq!(batches.len(), 2);
+        for (b, batch) in batches.iter().enumerate() {
+            let ticks = b * 10..(b + 1) * 10;
+            let float: Vec<_> = ticks.clone().map(|t| input(1, t)).collect();
+            assert_eq!(batch["float"], float);
+            let int16: Vec<_> = ticks.clone().map(|t| input(2, t)).collect();
+            assert_eq!(batch["int16"], int16);
+            for (delta, tick) in batch["delta"].iter().zip(ticks) {
+                assert!(
+                    (delta - input(3, tick)).abs() <= 0.005,
+                    "{} {}",
+                    delta,
+                    tick
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn script_length() {
+        let numbers = (0..MAX_INPUTS).map(|i| format!("{:_<31}:n:0.001:-1000", i));
+        let bools = (0..MAX_INPUTS).map(|i| format!("{:_<30}b:b", i));
+        let channels: Vec<_> = numbers.chain(bools).collect();
+        let channels: Vec<_> = channels.iter().map(String::as_str).collect();
+        let mut options = options(&channels);
+        options.token = Some("t".repeat(64));
+        assert!(generate(&options).unwrap().len() <= MAX_SCRIPT_LEN);
+
+        options.token = Some("t".repeat(MAX_SCRIPT_LEN));
+        let e = generate(&options).unwrap_err();
+        assert!(
+            e.ends_with(&format!("exceeding {}", MAX_SCRIPT_LEN)),
+            "{}",
+            e
+        );
+    }
+}
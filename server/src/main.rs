@@ -1,20 +1,51 @@
+mod alerts;
 mod args;
+mod auth;
+mod codec;
+mod db;
+mod export;
+mod flow;
+mod fragments;
+mod grafana;
+mod luagen;
+mod mcgen;
+mod mqtt;
+mod query;
+mod recorder;
+mod relay;
+mod rollup;
+mod sequence;
+mod sessions;
+mod simulate;
+mod sink;
+mod table;
+mod tls;
 mod values;
 
 use axum::{
     extract::{
         ws::{Message, WebSocket, WebSocketUpgrade},
-        Query, RawQuery, State,
+        Path, Query, RawQuery, State,
+    },
+    http::{header, Request, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse,
     },
-    http::{Request, StatusCode},
-    response::IntoResponse,
     routing::get,
     Json, Router,
 };
 use clap::Parser;
 use futures::{prelude::*, SinkExt};
-use std::{collections::HashMap, net::SocketAddrV4, ops::Deref, sync::Arc};
-use tokio::sync::{broadcast, Mutex};
+use serde::Serialize;
+use std::{
+    collections::{HashMap, HashSet},
+    convert::Infallible,
+    net::SocketAddrV4,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::sync::{broadcast, mpsc, Mutex};
 use tokio_stream::wrappers::BroadcastStream;
 
 #[tokio::main]
@@ -24,21 +55,98 @@ async fn main() {
         .init();
 
     let args = args::Args::parse();
+    if let Some(command) = &args.command {
+        let output = match command {
+            args::Command::GenLua(options) => luagen::generate(options),
+            args::Command::GenMc(options) => mcgen::generate(options),
+            args::Command::Export(options) => db::export(options),
+            args::Command::Import(options) => db::import(options),
+            args::Command::ExportParquet(options) => export::run(options),
+            args::Command::Simulate(options) => {
+                simulate::run(options).await.map(|()| String::new())
+            }
+        };
+        match output {
+            Ok(output) => return print!("{}", output),
+            Err(e) => {
+                log::error!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
 
-    let state = Arc::new(AppState::new());
-    let app = Router::new()
-        .route("/socket", get(websocket_handler))
+    let state = match AppState::new(&args) {
+        Ok(state) => Arc::new(state),
+        Err(e) => {
+            log::error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    if !state.alerts.lock().await.is_empty() {
+        let state = state.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(alerts::STALE_INTERVAL);
+            loop {
+                interval.tick().await;
+                let alerts = state.alerts.lock().await.check_stale();
+                state.alert(alerts).await;
+            }
+        });
+    }
+    let writer = Router::new()
         .route("/push", get(push_handler))
         .route("/p", get(push_handler2))
+        .route("/annotate", get(annotate))
+        .route("/session/new", get(new_session))
+        .route("/recording/start", get(start_recording))
+        .route("/recording/arm", get(arm_recording))
+        .route("/recording/stop", get(stop_recording))
+        .route_layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            auth::require_write,
+        ));
+    let reader = Router::new()
+        .route("/socket", get(websocket_handler))
+        .route("/events", get(events))
         .route("/download.json", get(download_json))
+        .route("/download.csv", get(download_csv))
+        .route("/download.jsonl", get(download_jsonl))
+        .route("/download.parquet", get(download_parquet))
+        .route("/status", get(status))
+        .route("/history/:key", get(history))
+        .route("/keys", get(keys))
+        .route("/series/:key", get(series))
+        .route("/query", get(query))
+        .route("/sessions", get(sessions))
+        .nest("/grafana", grafana::router())
+        .route_layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            auth::require_read,
+        ));
+    let app = Router::new()
+        .merge(writer)
+        .merge(reader)
         .with_state(state)
         .nest_service("/", tower_http::services::ServeDir::new("public"))
         .layer(axum::middleware::from_fn(access_log));
 
-    axum::Server::bind(&SocketAddrV4::new(args.ip, args.port).into())
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
+    let http = axum::Server::bind(&SocketAddrV4::new(args.ip, args.port).into())
+        .serve(app.clone().into_make_service());
+    if let Some(tls_port) = args.tls_port {
+        let config = match tls::config(&args).await {
+            Ok(config) => config,
+            Err(e) => {
+                log::error!("failed to load the TLS certificate: {}", e);
+                std::process::exit(1);
+            }
+        };
+        let https =
+            axum_server::bind_rustls(SocketAddrV4::new(args.tls_ip, tls_port).into(), config)
+                .serve(app.into_make_service());
+        futures::try_join!(http.map_err(std::io::Error::other), https).unwrap();
+    } else {
+        http.await.unwrap();
+    }
 }
 
 async fn access_log<B>(
@@ -49,38 +157,194 @@ async fn access_log<B>(
     Ok(next.run(req).await)
 }
 
+const BROADCAST_CAPACITY: usize = 100;
+/// Commands waiting for a `/p` reply
+const COMMAND_CAPACITY: usize = 64;
+
 struct AppState {
     tx: broadcast::Sender<Message>,
-    values: Mutex<values::Values>,
+    values: Arc<values::Values>,
+    fragments: Mutex<fragments::Reassembler>,
+    sequences: Mutex<sequence::Sequences>,
+    flow: Mutex<flow::Flow>,
+    tokens: auth::Tokens,
+    db: Option<Arc<db::Db>>,
+    sessions: Mutex<sessions::Sessions>,
+    recorder: Mutex<recorder::Recorder>,
+    alerts: Mutex<alerts::Alerts>,
+    sinks: Vec<Box<dyn sink::Sink>>,
+    /// passed to the game one per `/p` reply
+    commands: Mutex<mpsc::Receiver<String>>,
 }
 
 impl AppState {
-    pub fn new() -> AppState {
-        let (tx, _) = broadcast::channel(100);
-        AppState {
+    pub fn new(args: &args::Args) -> Result<AppState, String> {
+        let (tx, _) = broadcast::channel(BROADCAST_CAPACITY);
+        sink::check(&args.sinks, args.db.is_some())?;
+        let db = args
+            .db
+            .as_deref()
+            .map(db::Db::open)
+            .transpose()?
+            .map(Arc::new);
+        let session = db.as_ref().map_or(1, |db| db.session());
+        let values = Arc::new(values::Values::default());
+        let (commands_tx, commands) = mpsc::channel(COMMAND_CAPACITY);
+        let sinks = sink::build(args, &values, &db, &tx, &commands_tx)?;
+        Ok(AppState {
             tx,
-            values: Default::default(),
+            values,
+            fragments: Mutex::new(fragments::Reassembler::new(Duration::from_secs(
+                args.fragment_timeout,
+            ))),
+            sequences: Default::default(),
+            flow: Default::default(),
+            tokens: auth::Tokens {
+                read: args.read_token.clone(),
+                write: args.write_token.clone(),
+            },
+            db,
+            sessions: Mutex::new(sessions::Sessions::new(
+                session,
+                Duration::from_secs(args.session_gap),
+            )),
+            recorder: Mutex::new(recorder::Recorder::new(if args.idle {
+                recorder::State::Idle
+            } else {
+                recorder::State::Recording
+            })),
+            alerts: Mutex::new(alerts::Alerts::new(&args.alerts)?),
+            sinks,
+            commands: Mutex::new(commands),
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct Status {
+    sources: HashMap<String, sequence::SourceStats>,
+    links: HashMap<String, flow::LinkStats>,
+    tick: u64,
+    recording: recorder::Status,
+    session: i64,
+}
+
+impl AppState {
+    async fn status(&self) -> Status {
+        Status {
+            sources: self.sequences.lock().await.stats(),
+            links: self.flow.lock().await.stats(),
+            tick: self.values.tick(),
+            recording: self.recorder.lock().await.status(),
+            session: self.sessions.lock().await.current(),
         }
     }
+
+    /// Dispatches the batch to the sinks, first starting a new session when
+    /// a trigger fired or after an idle gap
+    async fn ingest(&self, batch: &HashMap<String, Vec<f32>>) {
+        let alerts = self.alerts.lock().await.check(batch);
+        self.alert(alerts).await;
+        let output = self.recorder.lock().await.process(batch);
+        if output.triggered {
+            log::info!("recording triggered");
+        }
+        if output.stopped {
+            log::info!("recording stopped after its duration");
+        }
+        let reason = {
+            let mut sessions = self.sessions.lock().await;
+            let reason = if output.triggered && !sessions.is_empty() {
+                Some("the trigger")
+            } else if sessions.idle() && !output.store.is_empty() {
+                Some("an idle gap")
+            } else {
+                None
+            };
+            if reason.is_some() {
+                sessions.starting();
+            }
+            reason
+        };
+        if let Some(reason) = reason {
+            match self.start_session().await {
+                Ok(id) => log::info!("session {} started by {}", id, reason),
+                Err(e) => log::error!("failed to start a session: {}", e),
+            }
+        }
+        if reason.is_some() || output.triggered || output.stopped {
+            self.tx.send(self.status_message().await).ok();
+        }
+        let batch = sink::Batch {
+            live: batch,
+            recorded: &output.store,
+        };
+        for sink in &self.sinks {
+            if let Err(e) = sink.write(&batch) {
+                log::error!("{} sink: {}", sink.name(), e);
+            }
+        }
+        // live-only pushes of an idle recorder don't extend the session
+        if !output.store.is_empty() {
+            self.sessions.lock().await.touch(output.store.keys());
+        }
+    }
+
+    async fn new_session(&self) -> Result<i64, String> {
+        let id = self.start_session().await?;
+        self.tx.send(self.status_message().await).ok();
+        Ok(id)
+    }
+
+    /// Moves the buffers to the archive and restarts at tick 0, without
+    /// holding the sessions while the database creates the session
+    async fn start_session(&self) -> Result<i64, String> {
+        let id = match &self.db {
+            Some(db) => Some(db.new_session().await?),
+            None => None,
+        };
+        let mut sessions = self.sessions.lock().await;
+        let id = id.unwrap_or(sessions.current() + 1);
+        sessions.start(id, self.values.take());
+        Ok(id)
+    }
+
+    /// Runs the actions of the alerts and broadcasts them as `{"alert": ...}`
+    async fn alert(&self, alerts: Vec<alerts::Alert>) {
+        if alerts.is_empty() {
+            return;
+        }
+        let actions = self.alerts.lock().await;
+        for alert in alerts {
+            actions.act(&alert);
+            let message = serde_json::json!({ "alert": alert });
+            self.tx.send(Message::Text(message.to_string())).ok();
+        }
+    }
+
+    /// `{"status": ...}` for WebSocket clients
+    async fn status_message(&self) -> Message {
+        let status = serde_json::json!({ "status": self.status().await });
+        Message::Text(status.to_string())
+    }
 }
 
 async fn push_handler(
-    Query(query): Query<Vec<(String, f32)>>,
+    Query(query): Query<Vec<(String, String)>>,
     State(state): State<Arc<AppState>>,
 ) -> impl IntoResponse {
     let mut map = HashMap::<String, Vec<f32>>::new();
-    let mut values = state.values.lock().await;
     for (k, v) in query {
-        values.push(k.clone(), &[v]);
-        map.entry(k).or_default().push(v);
-    }
-    match serde_json::to_string(&map) {
-        Ok(s) => {
-            state.tx.send(Message::Text(s)).ok();
-            "OK".into()
+        if k == "token" {
+            continue;
+        }
+        match v.parse::<f32>() {
+            Ok(v) => map.entry(k).or_default().push(v),
+            Err(e) => return format!("failed to parse value of {}: {}", k, e),
         }
-        Err(e) => format!("failed to encode json: {}", e),
     }
+    state.ingest(&map).await;
+    "OK".into()
 }
 
 async fn push_handler2(
@@ -88,31 +352,552 @@ async fn push_handler2(
     State(state): State<Arc<AppState>>,
 ) -> impl IntoResponse {
     use base64::prelude::*;
-    let v = match BASE64_URL_SAFE_NO_PAD.decode(query.unwrap_or_default()) {
+    let query = query.unwrap_or_default();
+    let query = query::PushQuery::parse(&query);
+    let v = match BASE64_URL_SAFE_NO_PAD.decode(query.payload) {
         Ok(v) => v,
         Err(e) => return format!("failed to decode base64: {}", e),
     };
-    let v = match rmp_serde::from_slice::<HashMap<String, Vec<f32>>>(&v) {
+    let v = match fragment_params(&query) {
+        Ok(None) => v,
+        Ok(Some((id, index, count))) => {
+            let source = query.get("src").unwrap_or_default();
+            let mut fragments = state.fragments.lock().await;
+            match fragments.insert(source, id, index, count, v) {
+                Ok(fragments::Fragment::Complete(v)) => v,
+                Ok(fragments::Fragment::Incomplete { missing }) => {
+                    let missing: Vec<_> = missing.iter().map(|i| i.to_string()).collect();
+                    return format!("MISSING {}", missing.join(","));
+                }
+                Err(e) => return e,
+            }
+        }
+        Err(e) => return e,
+    };
+    let version = match query.parse_param("v") {
+        Ok(v) => v.unwrap_or(1),
+        Err(e) => return e,
+    };
+    let mut v = match codec::decode(version, &v) {
         Ok(v) => v,
-        Err(e) => return format!("failed to decode message pack: {}", e),
+        Err(e) => return e,
     };
-    let mut values = state.values.lock().await;
-    for (k, v) in &v {
-        values.push(k.clone(), v);
+    let source = query.get("src").unwrap_or_default();
+    match query.parse_param("w") {
+        Ok(waited) => state.flow.lock().await.request(source, waited),
+        Err(e) => return e,
+    }
+    let run = match query.parse_param("r") {
+        Ok(run) => run,
+        Err(e) => return e,
+    };
+    match query.parse_param::<u16>("s") {
+        Ok(Some(seq)) => {
+            let batch_len = v.values().map(|v| v.len()).max().unwrap_or_default();
+            let verdict = state
+                .sequences
+                .lock()
+                .await
+                .receive(source, run, seq, batch_len);
+            if verdict != sequence::Verdict::InOrder {
+                state.tx.send(state.status_message().await).ok();
+            }
+            match verdict {
+                sequence::Verdict::InOrder => {}
+                sequence::Verdict::Gap { batches, ticks } => {
+                    log::warn!("{} batches lost before {} from {:?}", batches, seq, source);
+                    // a longer gap would only push the buffers out
+                    let gap = (batches as usize * ticks).min(state.values.capacity());
+                    for v in v.values_mut() {
+                        v.splice(0..0, std::iter::repeat_n(f32::NAN, gap));
+                    }
+                }
+                sequence::Verdict::Duplicate => return "DUP".into(),
+                sequence::Verdict::Late => return "LATE".into(),
+            }
+        }
+        Ok(None) => {}
+        Err(e) => return e,
     }
-    match serde_json::to_string(&v) {
-        Ok(s) => {
-            state.tx.send(Message::Text(s)).ok();
+    state.ingest(&v).await;
+    let tick = state.values.tick();
+    let busy = state.tx.len() > BROADCAST_CAPACITY / 2;
+    let recorder = state.recorder.lock().await.state();
+    // armed recorders need fresh samples for their trigger too
+    let active = recorder != recorder::State::Idle;
+    let batch = state.flow.lock().await.reply(source, busy, active);
+    let recording = recorder == recorder::State::Recording;
+    let mut reply = format!("OK b={} r={} t={}", batch, recording as u8, tick);
+    if let Ok(command) = state.commands.lock().await.try_recv() {
+        reply += &format!(" c={}", command);
+    }
+    reply
+}
+
+/// `m` (message id), `i` (part index) and `n` (part count) of a split message
+fn fragment_params(query: &query::PushQuery) -> Result<Option<(u32, usize, usize)>, String> {
+    match (
+        query.parse_param("m")?,
+        query.parse_param("i")?,
+        query.parse_param("n")?,
+    ) {
+        (Some(id), Some(index), Some(count)) => Ok(Some((id, index, count))),
+        (None, None, None) => Ok(None),
+        _ => Err("m, i and n must be given together".into()),
+    }
+}
+
+/// Buffers of the current or a past `?session=`
+async fn download_json(
+    Query(query): Query<HashMap<String, String>>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let snapshot = match session_param(&state, &query).await? {
+        None => state.values.snapshot(),
+        Some(id) => {
+            let archived = state
+                .sessions
+                .lock()
+                .await
+                .archived(id)
+                .map(|v| v.snapshot());
+            match archived {
+                Some(snapshot) => snapshot,
+                None => with_db(state, move |conn| db::snapshot(conn, id)).await?,
+            }
+        }
+    };
+    // encoding hours of samples would stall the pushes sharing the worker
+    let json = tokio::task::spawn_blocking(move || serde_json::to_vec(&snapshot))
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|json| json.map_err(|e| e.to_string()))
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    Ok(([(header::CONTENT_TYPE, "application/json")], json))
+}
+
+/// `?keys=a,b` of the current or a past `?session=` as CSV
+async fn download_csv(
+    Query(query): Query<HashMap<String, String>>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let tables = session_tables(state, &query).await?;
+    Ok((
+        [(header::CONTENT_TYPE, "text/csv")],
+        stream_rows(
+            tables,
+            Some(table::Table::csv_header),
+            table::Table::csv_row,
+        ),
+    ))
+}
+
+/// `?keys=a,b` of the current or a past `?session=` as JSON Lines
+async fn download_jsonl(
+    Query(query): Query<HashMap<String, String>>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let tables = session_tables(state, &query).await?;
+    Ok((
+        [(header::CONTENT_TYPE, "application/jsonl")],
+        stream_rows(tables, None, table::Table::jsonl_row),
+    ))
+}
+
+/// `?keys=a,b` of the current or a past `?session=` as Apache Parquet,
+/// sent a row group at a time
+async fn download_parquet(
+    Query(query): Query<HashMap<String, String>>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let tables = session_tables(state, &query).await?;
+    let (tx, rx) = mpsc::channel(4);
+    tokio::task::spawn_blocking(move || {
+        let mut writer = None;
+        let result = tables
+            .each(export::ROWS_PER_GROUP, |table| {
+                let writer = match &mut writer {
+                    Some(writer) => writer,
+                    None => writer.insert(export::Writer::new(
+                        table.keys(),
+                        ChannelWriter(tx.clone()),
+                    )?),
+                };
+                writer.write(&table)
+            })
+            .and_then(|()| writer.ok_or("no rows".to_owned())?.close().map(drop));
+        if let Err(e) = result {
+            log::warn!("download stopped: {}", e);
+            tx.blocking_send(Err(e)).ok();
+        }
+    });
+    Ok((
+        [(header::CONTENT_TYPE, "application/vnd.apache.parquet")],
+        axum::body::StreamBody::new(tokio_stream::wrappers::ReceiverStream::new(rx)),
+    ))
+}
+
+/// Passes everything written on to a response body, blocking while the
+/// client is behind
+struct ChannelWriter(mpsc::Sender<Result<Vec<u8>, String>>);
+
+impl std::io::Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0
+            .blocking_send(Ok(buf.to_vec()))
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::BrokenPipe, "client is gone"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Encodes the rows on a blocking thread only as fast as the client reads
+/// them, a failing database read ends the body early
+fn stream_rows(
+    tables: Tables,
+    header: Option<fn(&table::Table) -> String>,
+    row: fn(&table::Table, u64) -> String,
+) -> axum::body::StreamBody<impl Stream<Item = Result<String, String>>> {
+    const ROWS_PER_CHUNK: u64 = 1024;
+    let (tx, rx) = mpsc::channel(4);
+    tokio::task::spawn_blocking(move || {
+        let mut header = header;
+        let result = tables.each(TICKS_PER_TABLE, |table| {
+            let header = header.take().map(|header| header(&table));
+            let ticks = table.ticks();
+            let chunks = ticks.clone().step_by(ROWS_PER_CHUNK as usize).map(|first| {
+                let mut chunk = String::new();
+                for tick in first..(first + ROWS_PER_CHUNK).min(ticks.end) {
+                    chunk += &row(&table, tick);
+                }
+                chunk
+            });
+            for chunk in header.into_iter().chain(chunks) {
+                // the client is gone
+                tx.blocking_send(Ok(chunk)).map_err(|e| e.to_string())?;
+            }
+            Ok(())
+        });
+        if let Err(e) = result {
+            log::warn!("download stopped: {}", e);
+            tx.blocking_send(Err(e)).ok();
+        }
+    });
+    axum::body::StreamBody::new(tokio_stream::wrappers::ReceiverStream::new(rx))
+}
+
+/// Ticks of a database session held in memory at a time while downloading
+const TICKS_PER_TABLE: u64 = 4096;
+
+/// Rows of a download
+enum Tables {
+    /// buffers of the current or an archived session
+    Memory(table::Table),
+    /// a session of the database, read as the rows are sent
+    Db {
+        conn: rusqlite::Connection,
+        session: i64,
+        keys: Option<Vec<String>>,
+        ticks: (Option<u64>, Option<u64>),
+    },
+}
+
+impl Tables {
+    /// Passes on the rows in tables of up to `ticks` ticks, at least one
+    fn each(
+        self,
+        ticks: u64,
+        mut f: impl FnMut(table::Table) -> Result<(), String>,
+    ) -> Result<(), String> {
+        match self {
+            Tables::Memory(table) => f(table),
+            Tables::Db {
+                conn,
+                session,
+                keys,
+                ticks: range,
+            } => db::tables(&conn, session, keys, range, ticks, f),
+        }
+    }
+}
+
+/// Buffers of `?keys=a,b` or all keys of the current or a past `?session=`
+/// from tick `?from=` up to `?to=`
+async fn session_tables(
+    state: Arc<AppState>,
+    query: &HashMap<String, String>,
+) -> Result<Tables, (StatusCode, String)> {
+    let keys = query
+        .get("keys")
+        .map(|keys| keys.split(',').map(str::to_owned).collect());
+    let tick = |name| {
+        query
+            .get(name)
+            .map(|t| t.parse::<u64>())
+            .transpose()
+            .map_err(|e| (StatusCode::BAD_REQUEST, format!("{}: {}", name, e)))
+    };
+    let (from, to) = (tick("from")?, tick("to")?);
+    let (buffers, started) = match session_param(&state, query).await? {
+        None => {
+            let sessions = state.sessions.lock().await;
+            let started = sessions.get(sessions.current()).and_then(|s| s.started);
+            (state.values.buffers(), started.unwrap_or_default())
+        }
+        Some(id) => {
+            let archived = {
+                let sessions = state.sessions.lock().await;
+                sessions.archived(id).map(|values| {
+                    let started = sessions.get(id).and_then(|s| s.started);
+                    (values.buffers(), started.unwrap_or_default())
+                })
+            };
+            match archived {
+                Some(archived) => archived,
+                None => {
+                    // fails for unknown sessions before any row is sent
+                    with_db(state.clone(), move |conn| db::started(conn, id)).await?;
+                    let conn = db_reader(&state)?;
+                    return Ok(Tables::Db {
+                        conn,
+                        session: id,
+                        keys,
+                        ticks: (from, to),
+                    });
+                }
+            }
+        }
+    };
+    Ok(Tables::Memory(
+        table::Table::new(buffers, keys, started).limit(from, to),
+    ))
+}
+
+async fn session_param(
+    state: &AppState,
+    query: &HashMap<String, String>,
+) -> Result<Option<i64>, (StatusCode, String)> {
+    let Some(session) = query.get("session") else {
+        return Ok(None);
+    };
+    let session = session
+        .parse()
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("session: {}", e)))?;
+    let current = state.sessions.lock().await.current();
+    Ok((session != current).then_some(session))
+}
+
+async fn new_session(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match state.new_session().await {
+        Ok(id) => format!("OK {}", id),
+        Err(e) => e,
+    }
+}
+
+/// `?duration=` in seconds after which the recording stops
+async fn start_recording(
+    Query(query): Query<HashMap<String, String>>,
+    State(state): State<Arc<AppState>>,
+) -> impl IntoResponse {
+    let duration = match seconds_param(&query, "duration") {
+        Ok(duration) => duration,
+        Err(e) => return e,
+    };
+    state.recorder.lock().await.start(duration);
+    let empty = state.sessions.lock().await.is_empty();
+    if !empty {
+        if let Err(e) = state.start_session().await {
+            return e;
+        }
+    }
+    state.tx.send(state.status_message().await).ok();
+    "OK".into()
+}
+
+/// Records from when `?trigger=` fires (`key>value`, `key<value` or a
+/// boolean `key`), keeping `?pre=` seconds before it, for `?duration=`
+async fn arm_recording(
+    Query(query): Query<HashMap<String, String>>,
+    State(state): State<Arc<AppState>>,
+) -> impl IntoResponse {
+    let trigger = match query.get("trigger").map(|t| t.parse()) {
+        Some(Ok(trigger)) => trigger,
+        Some(Err(e)) => return e,
+        None => return "missing trigger".into(),
+    };
+    let (pre, duration) = match (
+        seconds_param(&query, "pre"),
+        seconds_param(&query, "duration"),
+    ) {
+        (Ok(pre), Ok(duration)) => (pre.unwrap_or_default() as usize, duration),
+        (Err(e), _) | (_, Err(e)) => return e,
+    };
+    state.recorder.lock().await.arm(trigger, pre, duration);
+    state.tx.send(state.status_message().await).ok();
+    "OK".into()
+}
+
+async fn stop_recording(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    state.recorder.lock().await.stop();
+    state.tx.send(state.status_message().await).ok();
+    "OK"
+}
+
+/// Seconds in ticks
+fn seconds_param(query: &HashMap<String, String>, name: &str) -> Result<Option<u64>, String> {
+    query
+        .get(name)
+        .map(|v| {
+            v.parse::<f64>()
+                .map(|s| (s * 60.0).round() as u64)
+                .map_err(|e| format!("{}: {}", name, e))
+        })
+        .transpose()
+}
+
+async fn sessions(State(state): State<Arc<AppState>>) -> Json<Vec<sessions::Session>> {
+    Json(state.sessions.lock().await.list())
+}
+
+/// Stores `?text=` at the current tick of the session
+async fn annotate(
+    Query(query): Query<HashMap<String, String>>,
+    State(state): State<Arc<AppState>>,
+) -> impl IntoResponse {
+    let Some(db) = &state.db else {
+        return "annotations need --db".to_owned();
+    };
+    match query.get("text") {
+        Some(text) => {
+            db.annotate(state.values.tick(), text.clone());
             "OK".into()
         }
-        Err(e) => format!("failed to encode json: {}", e),
+        None => "missing text".into(),
     }
 }
 
-async fn download_json(State(state): State<Arc<AppState>>) -> Json<serde_json::Value> {
-    let values = state.values.lock().await;
-    let values = serde_json::to_value(values.deref()).unwrap();
-    Json(values)
+/// Samples of a key at `?res=raw|1s|1m` (default 1s) of the current or a
+/// past `?session=`
+async fn history(
+    Path(key): Path<String>,
+    Query(query): Query<HashMap<String, String>>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<rollup::History>, (StatusCode, String)> {
+    let resolution = query
+        .get("res")
+        .map_or(Ok(rollup::Resolution::Second), |r| r.parse())
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let session = session_param(&state, &query).await?;
+    match session_history(state, session, &key, resolution).await? {
+        Some(history) => Ok(Json(history)),
+        None => Err((StatusCode::NOT_FOUND, format!("unknown key {:?}", key))),
+    }
+}
+
+/// History of a key of the current session, a past one in memory or one in
+/// the database
+async fn session_history(
+    state: Arc<AppState>,
+    session: Option<i64>,
+    key: &str,
+    resolution: rollup::Resolution,
+) -> Result<Option<rollup::History>, (StatusCode, String)> {
+    let Some(id) = session else {
+        return Ok(state.values.history(key, resolution));
+    };
+    let archived = state
+        .sessions
+        .lock()
+        .await
+        .archived(id)
+        .map(|v| v.history(key, resolution));
+    match archived {
+        Some(history) => Ok(history),
+        None => {
+            let key = key.to_owned();
+            with_db(state, move |conn| db::history(conn, id, &key, resolution)).await
+        }
+    }
+}
+
+async fn keys(State(state): State<Arc<AppState>>) -> Json<Vec<values::Key>> {
+    Json(state.values.keys())
+}
+
+/// Buffer of a key as JSON, or CSV or msgpack if the `Accept` header asks for it
+async fn series(
+    Path(key): Path<String>,
+    headers: header::HeaderMap,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let Some(buffer) = state.values.buffer(&key) else {
+        return Err((StatusCode::NOT_FOUND, format!("unknown key {:?}", key)));
+    };
+    let accept = headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    let (content_type, body) = if accept.contains("text/csv") {
+        let mut csv = String::from("tick,value\n");
+        for (tick, v) in (buffer.start..).zip(&buffer.values) {
+            if v.is_nan() {
+                csv += &format!("{},\n", tick);
+            } else {
+                csv += &format!("{},{}\n", tick, v);
+            }
+        }
+        ("text/csv", csv.into_bytes())
+    } else if accept.contains("msgpack") {
+        let body = rmp_serde::to_vec_named(&buffer)
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        ("application/msgpack", body)
+    } else {
+        let body = serde_json::to_vec(&buffer)
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        ("application/json", body)
+    };
+    Ok(([(header::CONTENT_TYPE, content_type)], body))
+}
+
+/// Rows of a read-only `?sql=` statement on the database
+async fn query(
+    Query(query): Query<HashMap<String, String>>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<db::Rows>, (StatusCode, String)> {
+    let Some(sql) = query.get("sql").cloned() else {
+        return Err((StatusCode::BAD_REQUEST, "missing sql".into()));
+    };
+    with_db(state, move |conn| db::query(conn, &sql))
+        .await
+        .map(Json)
+}
+
+/// Read-only connection to the database of `--db`
+fn db_reader(state: &AppState) -> Result<rusqlite::Connection, (StatusCode, String)> {
+    let Some(db) = &state.db else {
+        return Err((StatusCode::NOT_FOUND, "no database, start with --db".into()));
+    };
+    db.reader()
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))
+}
+
+/// Runs `f` with a read-only connection on a blocking thread
+async fn with_db<T: Send + 'static>(
+    state: Arc<AppState>,
+    f: impl FnOnce(&rusqlite::Connection) -> Result<T, String> + Send + 'static,
+) -> Result<T, (StatusCode, String)> {
+    let conn = db_reader(&state)?;
+    tokio::task::spawn_blocking(move || f(&conn))
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))
+}
+
+async fn status(State(state): State<Arc<AppState>>) -> Json<Status> {
+    Json(state.status().await)
 }
 
 async fn websocket_handler(
@@ -122,10 +907,67 @@ async fn websocket_handler(
     ws.on_upgrade(|socket| websocket_worker(socket, state))
 }
 
-async fn websocket_worker(stream: WebSocket, state: Arc<AppState>) {
+async fn websocket_worker(mut stream: WebSocket, state: Arc<AppState>) {
     let rx = BroadcastStream::new(state.tx.subscribe());
+    if let Err(e) = stream.send(state.status_message().await).await {
+        log::info!("{}", e);
+        return;
+    }
     rx.map_err(|e| log::info!("{}", e))
         .forward(stream.sink_map_err(|e| log::info!("{}", e)))
         .await
         .ok();
 }
+
+/// The updates of `/socket` as server-sent events, `?keys=a,b` limits the
+/// values to these keys and `?snapshot=1` sends the buffered values first
+async fn events(
+    Query(query): Query<HashMap<String, String>>,
+    State(state): State<Arc<AppState>>,
+) -> impl IntoResponse {
+    let keys: Option<HashSet<String>> = query
+        .get("keys")
+        .map(|keys| keys.split(',').map(str::to_owned).collect());
+    let rx = BroadcastStream::new(state.tx.subscribe());
+    let mut first = vec![state.status_message().await];
+    if matches!(
+        query.get("snapshot").map(String::as_str),
+        Some("1" | "true")
+    ) {
+        let snapshot = state.values.snapshot();
+        match serde_json::to_string(&snapshot.values) {
+            Ok(s) => first.push(Message::Text(s)),
+            Err(e) => log::error!("failed to encode json: {}", e),
+        }
+    }
+    // like the socket, a lagging client is dropped and has to reconnect
+    let live = rx
+        .map_err(|e| log::info!("{}", e))
+        .take_while(|m| future::ready(m.is_ok()))
+        .filter_map(|m| future::ready(m.ok()));
+    let stream = stream::iter(first)
+        .chain(live)
+        .filter_map(move |m| future::ready(event_data(m, keys.as_ref())))
+        .map(|data| Ok::<_, Infallible>(Event::default().data(data)));
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+/// Text of a broadcast message with the values outside of `keys` removed
+fn event_data(message: Message, keys: Option<&HashSet<String>>) -> Option<String> {
+    let Message::Text(text) = message else {
+        return None;
+    };
+    let Some(keys) = keys else {
+        return Some(text);
+    };
+    // status and alert messages are objects rather than sample arrays
+    let Ok(mut values) = serde_json::from_str::<HashMap<String, Vec<serde_json::Value>>>(&text)
+    else {
+        return Some(text);
+    };
+    values.retain(|k, _| keys.contains(k));
+    if values.is_empty() {
+        return None;
+    }
+    serde_json::to_string(&values).ok()
+}
@@ -0,0 +1,221 @@
+//! Generator for a microcontroller XML embedding the script of [`crate::luagen`].
+//!
+//! The microcontroller has a single composite input node feeding the Lua
+//! block and one text property `label_N` per channel holding its key.
+use crate::luagen;
+use std::fmt::Write;
+
+/// Component type of a Lua script block
+const COMPONENT_LUA: u32 = 56;
+/// Component type of a text property
+const COMPONENT_PROPERTY_TEXT: u32 = 58;
+/// Bridge type of a composite input node
+const BRIDGE_COMPOSITE_INPUT: u32 = 4;
+const NODE_MODE_INPUT: u32 = 1;
+const NODE_TYPE_COMPOSITE: u32 = 5;
+
+#[derive(Debug, Clone, clap::Args)]
+#[group(skip)]
+pub struct Options {
+    #[command(flatten)]
+    pub script: luagen::Options,
+    /// Name of the microcontroller
+    #[arg(long, default_value = "sw_logger")]
+    pub name: String,
+}
+
+pub fn generate(options: &Options) -> Result<String, String> {
+    let script = luagen::generate(&luagen::Options {
+        labels_from_properties: true,
+        ..options.script.clone()
+    })?;
+    let indices = luagen::input_indices(&options.script)?;
+
+    // ids: 1 = input bridge, 2 = Lua block, 3.. = properties
+    let channels = &options.script.channels;
+    let mut s = String::new();
+    writeln!(s, r#"<?xml version="1.0" encoding="UTF-8"?>"#).unwrap();
+    writeln!(
+        s,
+        r#"<microprocessor name="{}" description="{}" width="1" length="1" id_counter="{}" id_counter_node="1">"#,
+        escape(&options.name),
+        escape("pushes the composite input to sw_logger_server"),
+        channels.len() + 2
+    )
+    .unwrap();
+    writeln!(s, "\t<nodes>").unwrap();
+    writeln!(s, "\t\t<n id=\"1\" component_id=\"1\">").unwrap();
+    writeln!(
+        s,
+        "\t\t\t<node label=\"Input\" mode=\"{}\" type=\"{}\" description=\"{}\">",
+        NODE_MODE_INPUT,
+        NODE_TYPE_COMPOSITE,
+        escape(&describe_inputs(options, &indices))
+    )
+    .unwrap();
+    writeln!(s, "\t\t\t\t<position x=\"0\" z=\"0\"/>").unwrap();
+    writeln!(s, "\t\t\t</node>").unwrap();
+    writeln!(s, "\t\t</n>").unwrap();
+    writeln!(s, "\t</nodes>").unwrap();
+    writeln!(s, "\t<group>").unwrap();
+    writeln!(s, "\t\t<data>").unwrap();
+    writeln!(s, "\t\t\t<inputs/>").unwrap();
+    writeln!(s, "\t\t\t<outputs/>").unwrap();
+    writeln!(s, "\t\t</data>").unwrap();
+    writeln!(s, "\t\t<components>").unwrap();
+    writeln!(s, "\t\t\t<c type=\"{}\">", COMPONENT_LUA).unwrap();
+    writeln!(
+        s,
+        "\t\t\t\t<object id=\"2\" script=\"{}\">",
+        escape(&script)
+    )
+    .unwrap();
+    writeln!(s, "\t\t\t\t\t<pos x=\"0\" y=\"0\"/>").unwrap();
+    writeln!(s, "\t\t\t\t\t<in1 component_id=\"1\"/>").unwrap();
+    writeln!(s, "\t\t\t\t</object>").unwrap();
+    writeln!(s, "\t\t\t</c>").unwrap();
+    for (i, channel) in channels.iter().enumerate() {
+        writeln!(s, "\t\t\t<c type=\"{}\">", COMPONENT_PROPERTY_TEXT).unwrap();
+        writeln!(
+            s,
+            "\t\t\t\t<object id=\"{}\" n=\"label_{}\" v=\"{}\">",
+            i + 3,
+            i + 1,
+            escape(&channel.name)
+        )
+        .unwrap();
+        writeln!(s, "\t\t\t\t\t<pos x=\"{}\" y=\"-2\"/>", i * 2).unwrap();
+        writeln!(s, "\t\t\t\t</object>").unwrap();
+        writeln!(s, "\t\t\t</c>").unwrap();
+    }
+    writeln!(s, "\t\t</components>").unwrap();
+    writeln!(s, "\t\t<components_bridge>").unwrap();
+    writeln!(s, "\t\t\t<c type=\"{}\">", BRIDGE_COMPOSITE_INPUT).unwrap();
+    writeln!(s, "\t\t\t\t<object id=\"1\">").unwrap();
+    writeln!(s, "\t\t\t\t\t<pos x=\"-2\" y=\"0\"/>").unwrap();
+    writeln!(s, "\t\t\t\t</object>").unwrap();
+    writeln!(s, "\t\t\t</c>").unwrap();
+    writeln!(s, "\t\t</components_bridge>").unwrap();
+    writeln!(s, "\t\t<groups/>").unwrap();
+    writeln!(s, "\t</group>").unwrap();
+    writeln!(s, "</microprocessor>").unwrap();
+    Ok(s)
+}
+
+/// e.g. `N1 speed, B2 gear`
+fn describe_inputs(options: &Options, indices: &[usize]) -> String {
+    let mut inputs: Vec<_> = options
+        .script
+        .channels
+        .iter()
+        .zip(indices)
+        .map(|(c, i)| match c.kind {
+            luagen::Kind::Number { .. } => format!("N{} {}", i, c.name),
+            luagen::Kind::Bool => format!("B{} {}", i, c.name),
+        })
+        .collect();
+    if let Some(e) = options.script.enable {
+        inputs.insert(0, format!("B{} enable", e));
+    }
+    inputs.join(", ")
+}
+
+/// Escapes an attribute value, keeping the line breaks of scripts
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\n' => escaped.push_str("&#x0A;"),
+            '\t' => escaped.push_str("&#x09;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    /// Paths like `microprocessor/group/components/c[56]/object` of all
+    /// elements, with the type of components
+    fn layout(xml: &roxmltree::Document) -> BTreeSet<String> {
+        xml.descendants()
+            .filter(|n| n.is_element())
+            .map(|n| {
+                let mut path: Vec<_> = n
+                    .ancestors()
+                    .filter(|a| a.is_element())
+                    .map(|a| match a.attribute("type") {
+                        Some(t) if a.has_tag_name("c") => format!("c[{}]", t),
+                        _ => a.tag_name().name().to_owned(),
+                    })
+                    .collect();
+                path.reverse();
+                path.join("/")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn microcontroller() {
+        let options = Options {
+            script: luagen::Options {
+                channels: ["speed:n:0.01", "gear", "on:b"]
+                    .iter()
+                    .map(|c| c.parse().unwrap())
+                    .collect(),
+                batch: 10,
+                port: 8080,
+                token: Some("a&b\"c".into()),
+                enable: None,
+                part_size: 180,
+                labels_from_properties: false,
+            },
+            name: "<logger>".into(),
+        };
+        let generated = generate(&options).unwrap();
+        let xml = roxmltree::Document::parse(&generated).unwrap();
+        let root = xml.root_element();
+        assert_eq!(root.attribute("name"), Some("<logger>"));
+
+        let script = xml
+            .descendants()
+            .find_map(|n| n.attribute("script"))
+            .unwrap();
+        let expected = luagen::generate(&luagen::Options {
+            labels_from_properties: true,
+            ..options.script.clone()
+        })
+        .unwrap();
+        assert_eq!(script, expected);
+
+        let labels: Vec<_> = xml
+            .descendants()
+            .filter_map(|n| Some((n.attribute("n")?, n.attribute("v")?)))
+            .collect();
+        assert_eq!(
+            labels,
+            [("label_1", "speed"), ("label_2", "gear"), ("label_3", "on")]
+        );
+        let ids: BTreeSet<u32> = xml
+            .descendants()
+            .filter(|n| n.has_tag_name("object"))
+            .map(|n| n.attribute("id").unwrap().parse().unwrap())
+            .collect();
+        assert_eq!(ids, (1..=5).collect());
+        assert_eq!(root.attribute("id_counter"), Some("5"));
+
+        // a composite input, a Lua block and a text property laid out like
+        // the game saves microcontrollers
+        let fixture = include_str!("../testdata/microcontroller.xml");
+        let fixture = roxmltree::Document::parse(fixture).unwrap();
+        let (layout, fixture) = (layout(&xml), layout(&fixture));
+        assert_eq!(layout, fixture, "{:#?}", layout.difference(&fixture));
+    }
+}
@@ -0,0 +1,260 @@
+//! Optional MQTT bridge publishing the live values to a broker and
+//! forwarding messages of its command topic to the game in `/p` replies.
+use crate::sink::{Batch, Sink};
+use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS};
+use std::{collections::HashSet, sync::Mutex, time::Duration};
+use tokio::sync::mpsc;
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, clap::Args)]
+#[group(id = "mqtt_options")]
+pub struct Options {
+    /// Broker of the mqtt sink as host:port
+    #[arg(long)]
+    pub mqtt: Option<String>,
+    /// The latest value of each key is published retained to <prefix>/<key>
+    /// and every batch as JSON to <prefix>/batch
+    #[arg(long, default_value = "sw_logger")]
+    pub mqtt_prefix: String,
+    /// Topic whose messages, such as `3=1.5` to set output number 3, are
+    /// passed to the game, <prefix>/command by default
+    #[arg(long)]
+    pub mqtt_command_topic: Option<String>,
+    #[arg(long, default_value = "sw_logger_server")]
+    pub mqtt_client_id: String,
+}
+
+pub struct Mqtt {
+    client: AsyncClient,
+    prefix: String,
+    /// keys with wildcards, which can't be topics, warned about once
+    skipped: Mutex<HashSet<String>>,
+}
+
+impl Mqtt {
+    /// Connects in the background, reconnecting on errors
+    pub fn connect(options: &Options, commands: mpsc::Sender<String>) -> Result<Self, String> {
+        let broker = options
+            .mqtt
+            .as_deref()
+            .ok_or("the mqtt sink needs --mqtt")?;
+        let (host, port) = broker
+            .rsplit_once(':')
+            .ok_or_else(|| format!("expected host:port, got {}", broker))?;
+        let port = port
+            .parse()
+            .map_err(|e| format!("mqtt port {:?}: {}", port, e))?;
+        if !rumqttc::valid_topic(&options.mqtt_prefix) {
+            return Err(format!(
+                "--mqtt-prefix {} must not contain + or #",
+                options.mqtt_prefix
+            ));
+        }
+        let mut mqtt_options = MqttOptions::new(&options.mqtt_client_id, host, port);
+        mqtt_options.set_keep_alive(Duration::from_secs(30));
+        let (client, mut eventloop) = AsyncClient::new(mqtt_options, 256);
+        let command_topic = options
+            .mqtt_command_topic
+            .clone()
+            .unwrap_or_else(|| format!("{}/command", options.mqtt_prefix));
+
+        let subscriber = client.clone();
+        let broker = broker.to_owned();
+        tokio::spawn(async move {
+            loop {
+                match eventloop.poll().await {
+                    // subscriptions of a clean session are gone after a reconnect
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                        log::info!("connected to mqtt broker {}", broker);
+                        // awaiting a full request queue here would never drain it
+                        if let Err(e) = subscriber.try_subscribe(&command_topic, QoS::AtLeastOnce) {
+                            log::error!("failed to subscribe to {}: {}", command_topic, e);
+                        }
+                    }
+                    Ok(Event::Incoming(Packet::Publish(publish))) => {
+                        if publish.topic == command_topic {
+                            forward(&publish.payload, &commands);
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        log::warn!("mqtt broker {}: {}", broker, e);
+                        tokio::time::sleep(RECONNECT_DELAY).await;
+                    }
+                }
+            }
+        });
+        Ok(Self {
+            client,
+            prefix: options.mqtt_prefix.clone(),
+            skipped: Default::default(),
+        })
+    }
+}
+
+/// Queues a command for the next `/p` reply
+fn forward(payload: &[u8], commands: &mpsc::Sender<String>) {
+    let command = match std::str::from_utf8(payload) {
+        Ok(command) => command.trim(),
+        Err(e) => return log::warn!("ignoring mqtt command: {}", e),
+    };
+    if command.is_empty() || command.contains(char::is_whitespace) {
+        return log::warn!("ignoring mqtt command {:?}", command);
+    }
+    if commands.try_send(command.to_owned()).is_err() {
+        log::warn!("dropping mqtt command {:?}, no game is polling", command);
+    }
+}
+
+impl Sink for Mqtt {
+    fn name(&self) -> &'static str {
+        "mqtt"
+    }
+
+    fn write(&self, batch: &Batch) -> Result<(), String> {
+        let e = |e: rumqttc::ClientError| e.to_string();
+        for (key, values) in batch.live {
+            if let Some(last) = values.iter().rev().find(|v| !v.is_nan()) {
+                let topic = format!("{}/{}", self.prefix, key);
+                if !rumqttc::valid_topic(&topic) {
+                    if self.skipped.lock().unwrap().insert(key.clone()) {
+                        log::warn!("not publishing {:?}, topics can't contain + or #", key);
+                    }
+                    continue;
+                }
+                self.client
+                    .try_publish(topic, QoS::AtMostOnce, true, last.to_string())
+                    .map_err(e)?;
+            }
+        }
+        let json = serde_json::to_vec(batch.live).map_err(|e| e.to_string())?;
+        self.client
+            .try_publish(
+                format!("{}/batch", self.prefix),
+                QoS::AtMostOnce,
+                false,
+                json,
+            )
+            .map_err(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    /// Topic, payload and retain flag of a publish
+    type Published = (String, String, bool);
+
+    async fn read_packet(stream: &mut TcpStream) -> std::io::Result<(u8, Vec<u8>)> {
+        let header = stream.read_u8().await?;
+        let (mut len, mut shift) = (0, 0);
+        loop {
+            let b = stream.read_u8().await?;
+            len |= ((b & 0x7f) as usize) << shift;
+            shift += 7;
+            if b & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut body = vec![0; len];
+        stream.read_exact(&mut body).await?;
+        Ok((header, body))
+    }
+
+    fn string(body: &[u8]) -> (String, &[u8]) {
+        let len = u16::from_be_bytes([body[0], body[1]]) as usize;
+        let s = String::from_utf8(body[2..2 + len].to_vec()).unwrap();
+        (s, &body[2 + len..])
+    }
+
+    /// Accepts a single client of MQTT 3.1.1 with QoS 0 publishes, sends
+    /// `command` to the first subscription and passes on what is published
+    async fn broker(listener: TcpListener, command: &str, published: mpsc::Sender<Published>) {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        while let Ok((header, body)) = read_packet(&mut stream).await {
+            match header >> 4 {
+                // CONNECT
+                1 => stream.write_all(&[0x20, 2, 0, 0]).await.unwrap(),
+                // PUBLISH
+                3 => {
+                    let (topic, payload) = string(&body);
+                    let payload = String::from_utf8(payload.to_vec()).unwrap();
+                    let retain = header & 1 == 1;
+                    published.send((topic, payload, retain)).await.unwrap();
+                }
+                // SUBSCRIBE
+                8 => {
+                    let (filter, _) = string(&body[2..]);
+                    stream
+                        .write_all(&[0x90, 3, body[0], body[1], 1])
+                        .await
+                        .unwrap();
+                    let mut publish = (filter.len() as u16).to_be_bytes().to_vec();
+                    publish.extend(filter.as_bytes());
+                    publish.extend(command.as_bytes());
+                    stream
+                        .write_all(&[0x30, publish.len() as u8])
+                        .await
+                        .unwrap();
+                    stream.write_all(&publish).await.unwrap();
+                }
+                // PINGREQ
+                12 => stream.write_all(&[0xd0, 0]).await.unwrap(),
+                _ => {}
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn broker_roundtrip() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let options = Options {
+            mqtt: Some(listener.local_addr().unwrap().to_string()),
+            mqtt_prefix: "test".into(),
+            mqtt_command_topic: None,
+            mqtt_client_id: "test".into(),
+        };
+        let (published_tx, mut published) = mpsc::channel(16);
+        tokio::spawn(broker(listener, "3=1.5", published_tx));
+        let (commands_tx, mut commands) = mpsc::channel(1);
+        let mqtt = Mqtt::connect(&options, commands_tx).unwrap();
+
+        let timeout = Duration::from_secs(5);
+        let command = tokio::time::timeout(timeout, commands.recv()).await;
+        assert_eq!(command.unwrap().as_deref(), Some("3=1.5"));
+
+        let live = HashMap::from([
+            ("a".to_owned(), vec![1.0, 2.0, f32::NAN]),
+            ("b/+".to_owned(), vec![1.0]),
+            ("c#".to_owned(), vec![1.0]),
+        ]);
+        let batch = Batch {
+            live: &live,
+            recorded: &live,
+        };
+        // wildcards are skipped instead of failing every batch
+        mqtt.write(&batch).unwrap();
+        mqtt.write(&batch).unwrap();
+
+        let mut received = vec![];
+        while received.len() < 4 {
+            let next = tokio::time::timeout(timeout, published.recv()).await;
+            received.push(next.unwrap().unwrap());
+        }
+        let value = ("test/a".to_owned(), "2".to_owned(), true);
+        assert_eq!(received[0], value);
+        assert_eq!(received[2], value);
+        for (topic, payload, retain) in [&received[1], &received[3]] {
+            assert_eq!((topic.as_str(), *retain), ("test/batch", false));
+            let json: HashMap<String, Vec<Option<f32>>> = serde_json::from_str(payload).unwrap();
+            assert_eq!(json.len(), 3);
+        }
+    }
+}
@@ -0,0 +1,38 @@
+/// Query of `/p?<payload>&name=value...`.
+///
+/// The payload is the only parameter without a value.
+pub struct PushQuery<'a> {
+    pub payload: &'a str,
+    params: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> PushQuery<'a> {
+    pub fn parse(query: &'a str) -> Self {
+        let mut payload = "";
+        let mut params = vec![];
+        for s in query.split('&') {
+            match s.split_once('=') {
+                Some(p) => params.push(p),
+                None if payload.is_empty() => payload = s,
+                None => {}
+            }
+        }
+        Self { payload, params }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&'a str> {
+        self.params
+            .iter()
+            .find(|(k, _)| *k == name)
+            .map(|(_, v)| *v)
+    }
+
+    pub fn parse_param<T: std::str::FromStr>(&self, name: &str) -> Result<Option<T>, String> {
+        self.get(name)
+            .map(|v| {
+                v.parse()
+                    .map_err(|_| format!("invalid value for {}: {}", name, v))
+            })
+            .transpose()
+    }
+}
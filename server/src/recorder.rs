@@ -0,0 +1,226 @@
+//! Recording control: pushes are only stored while recording, which starts
+//! on `/recording/start` or when the trigger of an armed recorder fires.
+use serde::Serialize;
+use std::{
+    collections::{HashMap, VecDeque},
+    str::FromStr,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum State {
+    Idle,
+    Armed,
+    Recording,
+}
+
+/// `key>value` or `key<value` fires when the key crosses the value,
+/// a bare `key` when a boolean becomes true
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trigger {
+    pub key: String,
+    pub rising: bool,
+    pub threshold: f32,
+}
+
+impl FromStr for Trigger {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (key, rising, threshold) = match s.split_once(['>', '<']) {
+            Some((key, threshold)) => (
+                key,
+                s.as_bytes()[key.len()] == b'>',
+                threshold
+                    .trim()
+                    .parse()
+                    .map_err(|e| format!("trigger threshold {:?}: {}", threshold, e))?,
+            ),
+            None => (s, true, 0.5),
+        };
+        let key = key.trim();
+        if key.is_empty() {
+            return Err("trigger needs a key".into());
+        }
+        Ok(Trigger {
+            key: key.to_owned(),
+            rising,
+            threshold,
+        })
+    }
+}
+
+impl std::fmt::Display for Trigger {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let op = if self.rising { '>' } else { '<' };
+        write!(f, "{}{}{}", self.key, op, self.threshold)
+    }
+}
+
+impl Trigger {
+    fn beyond(&self, v: f32) -> bool {
+        if self.rising {
+            v > self.threshold
+        } else {
+            v < self.threshold
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Status {
+    pub state: State,
+    pub trigger: Option<String>,
+    /// ticks kept before the trigger
+    pub pre_trigger: usize,
+    /// ticks after which the recording stops
+    pub duration: Option<u64>,
+    /// ticks recorded since the start or the trigger
+    pub recorded: u64,
+}
+
+/// Samples of a push to store
+#[derive(Debug, Default)]
+pub struct Output {
+    pub store: HashMap<String, Vec<f32>>,
+    /// the trigger fired, `store` starts with the pre-trigger samples
+    pub triggered: bool,
+    /// the duration elapsed, nothing is stored from now on
+    pub stopped: bool,
+}
+
+#[derive(Debug)]
+pub struct Recorder {
+    state: State,
+    trigger: Option<Trigger>,
+    pre_trigger: usize,
+    duration: Option<u64>,
+    recorded: u64,
+    buffer: HashMap<String, VecDeque<f32>>,
+    /// whether the trigger key was beyond the threshold at its last sample
+    beyond: Option<bool>,
+}
+
+impl Recorder {
+    pub fn new(state: State) -> Self {
+        Self {
+            state,
+            trigger: None,
+            pre_trigger: 0,
+            duration: None,
+            recorded: 0,
+            buffer: Default::default(),
+            beyond: None,
+        }
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    pub fn status(&self) -> Status {
+        Status {
+            state: self.state,
+            trigger: self.trigger.as_ref().map(|t| t.to_string()),
+            pre_trigger: self.pre_trigger,
+            duration: self.duration,
+            recorded: self.recorded,
+        }
+    }
+
+    pub fn arm(&mut self, trigger: Trigger, pre_trigger: usize, duration: Option<u64>) {
+        *self = Self {
+            trigger: Some(trigger),
+            pre_trigger,
+            duration,
+            ..Self::new(State::Armed)
+        };
+    }
+
+    pub fn start(&mut self, duration: Option<u64>) {
+        *self = Self {
+            duration,
+            ..Self::new(State::Recording)
+        };
+    }
+
+    pub fn stop(&mut self) {
+        *self = Self::new(State::Idle);
+    }
+
+    pub fn process(&mut self, batch: &HashMap<String, Vec<f32>>) -> Output {
+        match self.state {
+            State::Idle => Output::default(),
+            State::Armed => self.armed(batch),
+            State::Recording => {
+                let len = batch.values().map(|v| v.len()).max().unwrap_or_default();
+                self.record(batch.clone(), len as u64)
+            }
+        }
+    }
+
+    fn armed(&mut self, batch: &HashMap<String, Vec<f32>>) -> Output {
+        let Some(trigger) = &self.trigger else {
+            return Output::default();
+        };
+        let fired = batch.get(&trigger.key).and_then(|values| {
+            let index = values.iter().position(|v| {
+                if v.is_nan() {
+                    return false;
+                }
+                let beyond = trigger.beyond(*v);
+                let crossed = self.beyond == Some(false) && beyond;
+                self.beyond = Some(beyond);
+                crossed
+            })?;
+            Some((index, values.len() - index))
+        });
+        let Some((index, after)) = fired else {
+            for (key, values) in batch {
+                let buffer = self.buffer.entry(key.clone()).or_default();
+                buffer.extend(values);
+                let excess = buffer.len().saturating_sub(self.pre_trigger);
+                buffer.drain(..excess);
+            }
+            return Output::default();
+        };
+
+        let mut buffer = std::mem::take(&mut self.buffer);
+        let store = batch
+            .iter()
+            .map(|(key, values)| {
+                let mut samples: Vec<f32> = buffer.remove(key).unwrap_or_default().into();
+                let skip = (samples.len() + index).saturating_sub(self.pre_trigger);
+                samples.extend(values);
+                (key.clone(), samples.split_off(skip))
+            })
+            .collect();
+        self.state = State::Recording;
+        self.beyond = None;
+        let mut output = self.record(store, after as u64);
+        output.triggered = true;
+        output
+    }
+
+    /// `ticks` of `store` are recorded after the start or the trigger
+    fn record(&mut self, mut store: HashMap<String, Vec<f32>>, ticks: u64) -> Output {
+        self.recorded += ticks;
+        let mut stopped = false;
+        if let Some(duration) = self.duration {
+            if self.recorded >= duration {
+                let excess = (self.recorded - duration) as usize;
+                for values in store.values_mut() {
+                    values.truncate(values.len().saturating_sub(excess));
+                }
+                self.recorded = duration;
+                self.state = State::Idle;
+                stopped = true;
+            }
+        }
+        Output {
+            store,
+            triggered: false,
+            stopped,
+        }
+    }
+}
@@ -0,0 +1,175 @@
+//! Relay sink forwarding every ingested batch to the `/p` route of an
+//! upstream server, buffering batches while it is unavailable.
+use crate::sink::{Batch, Sink};
+use base64::prelude::*;
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::Notify;
+
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, clap::Args)]
+#[group(id = "relay_options")]
+pub struct Options {
+    /// Server the relay sink forwards to, like http://192.168.1.2:8080
+    #[arg(long)]
+    pub upstream: Option<String>,
+    /// Write token of the upstream server
+    #[arg(long)]
+    pub upstream_token: Option<String>,
+    /// Source name of the forwarded batches on the upstream server
+    #[arg(long, default_value = "relay")]
+    pub upstream_source: String,
+    /// Batches kept while the upstream is unavailable, the oldest are
+    /// dropped beyond this
+    #[arg(long, default_value = "10000")]
+    pub upstream_buffer: usize,
+}
+
+/// Encoded batch with its sequence number
+struct Pending {
+    seq: u16,
+    payload: String,
+}
+
+#[derive(Default)]
+struct Queue {
+    pending: VecDeque<Pending>,
+    seq: u16,
+    dropped: u64,
+}
+
+pub struct Relay {
+    queue: Arc<Mutex<Queue>>,
+    notify: Arc<Notify>,
+    capacity: usize,
+}
+
+impl Relay {
+    /// Starts forwarding in the background
+    pub fn start(options: &Options) -> Result<Self, String> {
+        let upstream = options
+            .upstream
+            .as_deref()
+            .ok_or("the relay sink needs --upstream")?
+            .trim_end_matches('/');
+        let mut base = format!("{}/p?", upstream);
+        let uri = base
+            .parse::<hyper::Uri>()
+            .map_err(|e| format!("--upstream {}: {}", upstream, e))?;
+        // the client has no TLS connector
+        if uri.scheme_str() != Some("http") {
+            return Err(format!(
+                "--upstream {}: only http:// is supported",
+                upstream
+            ));
+        }
+        // a new run, so the upstream doesn't take the batches for duplicates
+        let run = rand::random::<u32>().to_string();
+        let mut params = vec![("src", options.upstream_source.as_str()), ("r", &run)];
+        if let Some(token) = &options.upstream_token {
+            params.push(("token", token));
+        }
+        let params = serde_urlencoded::to_string(params).map_err(|e| e.to_string())?;
+
+        let relay = Self {
+            queue: Default::default(),
+            notify: Default::default(),
+            capacity: options.upstream_buffer.max(1),
+        };
+        let queue = relay.queue.clone();
+        let notify = relay.notify.clone();
+        base.push_str(&params);
+        tokio::spawn(forward(base, queue, notify));
+        Ok(relay)
+    }
+}
+
+/// Sends the oldest pending batch until the upstream accepts it, backing off
+/// while it fails
+async fn forward(base: String, queue: Arc<Mutex<Queue>>, notify: Arc<Notify>) {
+    let client = hyper::Client::new();
+    let mut delay = Duration::from_secs(1);
+    loop {
+        let next = {
+            let queue = queue.lock().unwrap();
+            queue
+                .pending
+                .front()
+                .map(|p| format!("{}&s={}&{}", base, p.seq, p.payload))
+        };
+        let Some(uri) = next else {
+            notify.notified().await;
+            continue;
+        };
+        let result = match uri.parse() {
+            Ok(uri) => match client.get(uri).await {
+                Ok(res) => {
+                    let status = res.status();
+                    hyper::body::to_bytes(res.into_body())
+                        .await
+                        .map(|body| (status, body))
+                        .map_err(|e| e.to_string())
+                }
+                Err(e) => Err(e.to_string()),
+            },
+            Err(e) => Err(format!("invalid url: {}", e)),
+        };
+        // DUP and LATE are batches the upstream got before
+        let accepted = match &result {
+            Ok((_, body)) => ["OK", "DUP", "LATE"]
+                .iter()
+                .any(|r| body.starts_with(r.as_bytes())),
+            Err(_) => false,
+        };
+        if accepted {
+            let mut queue = queue.lock().unwrap();
+            queue.pending.pop_front();
+            if queue.dropped > 0 {
+                log::warn!("upstream is back, {} batches were dropped", queue.dropped);
+                queue.dropped = 0;
+            }
+            delay = Duration::from_secs(1);
+            continue;
+        }
+        match result {
+            Ok((status, body)) => log::warn!(
+                "upstream replied {} {:?}",
+                status,
+                String::from_utf8_lossy(&body)
+            ),
+            Err(e) => log::warn!("upstream: {}", e),
+        }
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(MAX_RETRY_DELAY);
+    }
+}
+
+impl Sink for Relay {
+    fn name(&self) -> &'static str {
+        "relay"
+    }
+
+    fn write(&self, batch: &Batch) -> Result<(), String> {
+        if batch.live.is_empty() {
+            return Ok(());
+        }
+        let payload = rmp_serde::to_vec(batch.live).map_err(|e| e.to_string())?;
+        let payload = BASE64_URL_SAFE_NO_PAD.encode(payload);
+        let mut queue = self.queue.lock().unwrap();
+        queue.seq = queue.seq.wrapping_add(1);
+        let seq = queue.seq;
+        queue.pending.push_back(Pending { seq, payload });
+        if queue.pending.len() > self.capacity {
+            // the upstream fills the gap with lost samples
+            queue.pending.pop_front();
+            queue.dropped += 1;
+        }
+        drop(queue);
+        self.notify.notify_one();
+        Ok(())
+    }
+}
@@ -0,0 +1,148 @@
+//! Downsampled min/max/mean tiers kept per key next to the raw samples.
+use serde::Serialize;
+use std::{collections::VecDeque, str::FromStr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    Raw,
+    Second,
+    Minute,
+}
+
+impl FromStr for Resolution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(Resolution::Raw),
+            "1s" => Ok(Resolution::Second),
+            "1m" => Ok(Resolution::Minute),
+            s => Err(format!(
+                "unknown resolution {:?}, expected raw, 1s or 1m",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    min: f32,
+    max: f32,
+    sum: f32,
+    count: u32,
+}
+
+impl Default for Bucket {
+    fn default() -> Self {
+        Self {
+            min: f32::INFINITY,
+            max: f32::NEG_INFINITY,
+            sum: 0.0,
+            count: 0,
+        }
+    }
+}
+
+impl Bucket {
+    fn add(&mut self, v: f32) {
+        // lost samples don't count, a bucket without any stays empty
+        if v.is_nan() {
+            return;
+        }
+        self.min = self.min.min(v);
+        self.max = self.max.max(v);
+        self.sum += v;
+        self.count += 1;
+    }
+
+    fn get(&self) -> (f32, f32, f32) {
+        if self.count == 0 {
+            (f32::NAN, f32::NAN, f32::NAN)
+        } else {
+            (self.min, self.max, self.sum / self.count as f32)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Tier {
+    step: u64,
+    capacity: usize,
+    buckets: VecDeque<Bucket>,
+    /// tick of the first sample of the first bucket
+    start: u64,
+    /// samples in the last bucket
+    filled: u64,
+}
+
+impl Tier {
+    /// 1-second buckets for 4 hours of a key whose first sample is at `tick`
+    pub fn seconds(tick: u64) -> Self {
+        Self::new(60, 4 * 60 * 60, tick)
+    }
+
+    /// 1-minute buckets for 3 days of a key whose first sample is at `tick`
+    pub fn minutes(tick: u64) -> Self {
+        Self::new(60 * 60, 3 * 24 * 60, tick)
+    }
+
+    fn new(step: u64, capacity: usize, tick: u64) -> Self {
+        // buckets of all keys cover the same ticks, a key appearing within
+        // one starts partway into it
+        let skipped = tick % step;
+        let mut tier = Self {
+            step,
+            capacity,
+            buckets: VecDeque::new(),
+            start: tick - skipped,
+            filled: step,
+        };
+        if skipped > 0 {
+            tier.buckets.push_back(Bucket::default());
+            tier.filled = skipped;
+        }
+        tier
+    }
+
+    pub fn push(&mut self, values: &[f32]) {
+        for &v in values {
+            if self.filled == self.step {
+                if self.buckets.len() == self.capacity {
+                    self.buckets.pop_front();
+                    self.start += self.step;
+                }
+                self.buckets.push_back(Bucket::default());
+                self.filled = 0;
+            }
+            self.buckets.back_mut().unwrap().add(v);
+            self.filled += 1;
+        }
+    }
+
+    pub fn history(&self) -> History {
+        let mut history = History {
+            step: self.step,
+            start: self.start,
+            ..Default::default()
+        };
+        for b in &self.buckets {
+            let (min, max, mean) = b.get();
+            history.min.push(min);
+            history.max.push(max);
+            history.mean.push(mean);
+        }
+        history
+    }
+}
+
+/// Response of `/history/:key`, point `n` covers the ticks from
+/// `start + n * step`; the last point may be incomplete.
+#[derive(Debug, Default, Serialize)]
+pub struct History {
+    pub step: u64,
+    pub start: u64,
+    pub min: Vec<f32>,
+    pub max: Vec<f32>,
+    pub mean: Vec<f32>,
+}
@@ -0,0 +1,195 @@
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// Number of past sequence numbers remembered for duplicate detection
+const WINDOW: u16 = 64;
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct SourceStats {
+    pub received: u64,
+    pub lost: u64,
+    pub duplicated: u64,
+    pub reordered: u64,
+    pub last_seq: Option<u16>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    InOrder,
+    /// `batches` batches of `ticks` ticks each were skipped before this one
+    Gap {
+        batches: u16,
+        ticks: usize,
+    },
+    Duplicate,
+    Late,
+}
+
+#[derive(Debug, Default)]
+struct Tracker {
+    stats: SourceStats,
+    /// bit `n` is set when `last_seq - n` was received
+    seen: u64,
+    batch_len: usize,
+    /// `r` of the last batch, picked by the script when it is loaded
+    run: Option<u32>,
+}
+
+impl Tracker {
+    fn receive(&mut self, run: Option<u32>, seq: u16, batch_len: usize) -> Verdict {
+        if run.is_some() && run != self.run {
+            self.run = run;
+            self.restart(seq, batch_len);
+            return Verdict::InOrder;
+        }
+        let Some(last) = self.stats.last_seq else {
+            self.restart(seq, batch_len);
+            return Verdict::InOrder;
+        };
+        let diff = seq.wrapping_sub(last) as i16;
+        if diff > 0 {
+            let skipped = diff as u16 - 1;
+            self.seen = self.seen.checked_shl(diff as u32).unwrap_or(0) | 1;
+            self.stats.last_seq = Some(seq);
+            self.stats.received += 1;
+            let ticks = self.batch_len;
+            self.batch_len = batch_len;
+            if skipped == 0 {
+                return Verdict::InOrder;
+            }
+            self.stats.lost += skipped as u64;
+            return Verdict::Gap {
+                batches: skipped,
+                ticks,
+            };
+        }
+        let back = diff.unsigned_abs();
+        let seen = back < WINDOW && self.seen & (1 << back) != 0;
+        if back >= WINDOW || (run.is_none() && seq == 1 && !seen) {
+            // scripts without `r` count from 1 again after a restart
+            self.restart(seq, batch_len);
+            return Verdict::InOrder;
+        }
+        if seen {
+            self.stats.duplicated += 1;
+            Verdict::Duplicate
+        } else {
+            self.seen |= 1 << back;
+            self.stats.received += 1;
+            self.stats.lost = self.stats.lost.saturating_sub(1);
+            self.stats.reordered += 1;
+            Verdict::Late
+        }
+    }
+
+    fn restart(&mut self, seq: u16, batch_len: usize) {
+        self.stats.last_seq = Some(seq);
+        self.stats.received += 1;
+        self.seen = 1;
+        self.batch_len = batch_len;
+    }
+}
+
+/// Tracks the `s` parameter of `/p` per `src`, starting over when `r`
+/// changes.
+#[derive(Debug, Default)]
+pub struct Sequences {
+    sources: HashMap<String, Tracker>,
+}
+
+impl Sequences {
+    pub fn receive(
+        &mut self,
+        source: &str,
+        run: Option<u32>,
+        seq: u16,
+        batch_len: usize,
+    ) -> Verdict {
+        self.sources
+            .entry(source.to_owned())
+            .or_default()
+            .receive(run, seq, batch_len)
+    }
+
+    pub fn stats(&self) -> HashMap<String, SourceStats> {
+        self.sources
+            .iter()
+            .map(|(k, t)| (k.clone(), t.stats.clone()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn receive(sequences: &mut Sequences, run: Option<u32>, seqs: &[u16]) -> Vec<Verdict> {
+        seqs.iter()
+            .map(|seq| sequences.receive("a", run, *seq, 10))
+            .collect()
+    }
+
+    #[test]
+    fn wraps() {
+        let mut sequences = Sequences::default();
+        let verdicts = receive(&mut sequences, Some(7), &[65534, 65535, 0, 1, 2]);
+        assert_eq!(verdicts, [Verdict::InOrder; 5]);
+        assert_eq!(sequences.stats()["a"].lost, 0);
+    }
+
+    #[test]
+    fn gap() {
+        let mut sequences = Sequences::default();
+        let verdicts = receive(&mut sequences, Some(7), &[1, 2, 5]);
+        assert_eq!(
+            verdicts[2],
+            Verdict::Gap {
+                batches: 2,
+                ticks: 10
+            }
+        );
+        assert_eq!(sequences.stats()["a"].lost, 2);
+    }
+
+    #[test]
+    fn duplicate() {
+        let mut sequences = Sequences::default();
+        let verdicts = receive(&mut sequences, Some(7), &[1, 2, 3, 2, 3]);
+        assert_eq!(verdicts[3..], [Verdict::Duplicate, Verdict::Duplicate]);
+        assert_eq!(sequences.stats()["a"].duplicated, 2);
+    }
+
+    #[test]
+    fn late() {
+        let mut sequences = Sequences::default();
+        let verdicts = receive(&mut sequences, Some(7), &[1, 3, 2, 2]);
+        assert_eq!(verdicts[2..], [Verdict::Late, Verdict::Duplicate]);
+        let stats = &sequences.stats()["a"];
+        assert_eq!((stats.lost, stats.reordered), (0, 1));
+    }
+
+    #[test]
+    fn restart() {
+        let mut sequences = Sequences::default();
+        receive(&mut sequences, Some(7), &[1, 2, 3, 4, 5]);
+        // a new run repeating numbers of the old one isn't a duplicate
+        let verdicts = receive(&mut sequences, Some(8), &[1, 2, 3]);
+        assert_eq!(verdicts, [Verdict::InOrder; 3]);
+        assert_eq!(sequences.stats()["a"].duplicated, 0);
+        assert_eq!(receive(&mut sequences, Some(8), &[2]), [Verdict::Duplicate]);
+    }
+
+    #[test]
+    fn restart_without_run() {
+        let mut sequences = Sequences::default();
+        let seqs: Vec<u16> = (1..=100).collect();
+        receive(&mut sequences, None, &seqs);
+        // 1 left the window, so it can only be a restart
+        assert_eq!(
+            receive(&mut sequences, None, &[1, 2]),
+            [Verdict::InOrder; 2]
+        );
+        // within the window it can't be told from a duplicate
+        assert_eq!(receive(&mut sequences, None, &[1]), [Verdict::Duplicate]);
+    }
+}
@@ -0,0 +1,133 @@
+//! Sessions split the ingested data at idle gaps or on `/session/new`.
+use crate::values::Values;
+use serde::Serialize;
+use std::{
+    collections::{BTreeSet, VecDeque},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Past sessions whose buffers are kept in memory
+const MAX_ARCHIVED: usize = 8;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Session {
+    pub id: i64,
+    /// unix time in seconds of the first and the last push
+    pub started: Option<u64>,
+    pub ended: Option<u64>,
+    pub keys: BTreeSet<String>,
+    /// whether the samples are still in memory, otherwise only in the database
+    pub archived: bool,
+}
+
+impl Session {
+    fn new(id: i64) -> Self {
+        Self {
+            id,
+            started: None,
+            ended: None,
+            keys: Default::default(),
+            archived: true,
+        }
+    }
+}
+
+pub struct Sessions {
+    current: Session,
+    gap: Option<Duration>,
+    last_push: Option<Instant>,
+    past: Vec<Session>,
+    archive: VecDeque<(i64, Values)>,
+}
+
+impl Sessions {
+    /// `gap` of zero never starts a session on its own
+    pub fn new(id: i64, gap: Duration) -> Self {
+        Self {
+            current: Session::new(id),
+            gap: (!gap.is_zero()).then_some(gap),
+            last_push: None,
+            past: vec![],
+            archive: VecDeque::new(),
+        }
+    }
+
+    pub fn current(&self) -> i64 {
+        self.current.id
+    }
+
+    /// Whether nothing was stored in the current session yet
+    pub fn is_empty(&self) -> bool {
+        self.current.keys.is_empty()
+    }
+
+    /// Whether nothing was pushed for longer than the gap
+    pub fn idle(&self) -> bool {
+        match (self.gap, self.last_push) {
+            (Some(gap), Some(last_push)) => last_push.elapsed() > gap,
+            _ => false,
+        }
+    }
+
+    pub fn touch<'a>(&mut self, keys: impl Iterator<Item = &'a String>) {
+        let now = unix_time();
+        self.last_push = Some(Instant::now());
+        self.current.started.get_or_insert(now);
+        self.current.ended = Some(now);
+        for key in keys {
+            if !self.current.keys.contains(key) {
+                self.current.keys.insert(key.clone());
+            }
+        }
+    }
+
+    /// Keeps [`Self::idle`] from starting another session until the one
+    /// being started replaces the current one
+    pub fn starting(&mut self) {
+        self.last_push = None;
+    }
+
+    /// Closes the current session with its buffers and starts `id`
+    pub fn start(&mut self, id: i64, values: Values) {
+        let current = std::mem::replace(&mut self.current, Session::new(id));
+        self.archive.push_back((current.id, values));
+        self.past.push(current);
+        self.last_push = None;
+        if self.archive.len() > MAX_ARCHIVED {
+            if let Some((id, _)) = self.archive.pop_front() {
+                if let Some(s) = self.past.iter_mut().find(|s| s.id == id) {
+                    s.archived = false;
+                }
+            }
+        }
+    }
+
+    /// Sessions since the server started, the current one last
+    pub fn list(&self) -> Vec<Session> {
+        let mut list = self.past.clone();
+        list.push(self.current.clone());
+        list
+    }
+
+    /// The current or a past session
+    pub fn get(&self, id: i64) -> Option<&Session> {
+        std::iter::once(&self.current)
+            .chain(&self.past)
+            .find(|s| s.id == id)
+    }
+
+    /// Buffers of a past session still in memory
+    pub fn archived(&self, id: i64) -> Option<&Values> {
+        self.archive
+            .iter()
+            .find(|(i, _)| *i == id)
+            .map(|(_, values)| values)
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
@@ -0,0 +1,435 @@
+//! Synthetic sources pushing generated signals through `/p`.
+use base64::prelude::*;
+use rand::{Rng, SeedableRng};
+use std::{
+    collections::HashMap,
+    f32::consts::TAU,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Kind {
+    Sine { period: f32 },
+    Square { period: f32 },
+    Noise,
+    Walk { step: f32 },
+    Steps { values: Vec<f32>, hold: f32 },
+    Bool { period: f32 },
+}
+
+/// `name=kind[:param=value...]`, e.g. `rpm=sine:period=4:amplitude=100:offset=500`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signal {
+    pub name: String,
+    pub kind: Kind,
+    pub amplitude: f32,
+    pub offset: f32,
+}
+
+impl FromStr for Signal {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, spec) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected name=kind: {}", s))?;
+        let mut it = spec.split(':');
+        let kind = it.next().unwrap_or_default();
+        let mut params = HashMap::new();
+        for p in it {
+            let (k, v) = p
+                .split_once('=')
+                .ok_or_else(|| format!("expected param=value: {}", p))?;
+            params.insert(k, v);
+        }
+        let number = |name: &str, default: f32| {
+            params
+                .get(name)
+                .map(|v| v.parse::<f32>().map_err(|e| format!("{}: {}", name, e)))
+                .unwrap_or(Ok(default))
+        };
+        let positive = |name: &str, default: f32| match number(name, default)? {
+            v if v > 0.0 && v.is_finite() => Ok(v),
+            _ => Err(format!("{} must be positive", name)),
+        };
+        let kind = match kind {
+            "sine" => Kind::Sine {
+                period: positive("period", 1.0)?,
+            },
+            "square" => Kind::Square {
+                period: positive("period", 1.0)?,
+            },
+            "noise" => Kind::Noise,
+            "walk" => Kind::Walk {
+                step: positive("step", 0.1)?,
+            },
+            "steps" => Kind::Steps {
+                values: params
+                    .get("values")
+                    .unwrap_or(&"0/1")
+                    .split('/')
+                    .map(|v| v.parse::<f32>().map_err(|e| format!("values: {}", e)))
+                    .collect::<Result<_, _>>()?,
+                hold: positive("hold", 1.0)?,
+            },
+            "bool" => Kind::Bool {
+                period: positive("period", 1.0)?,
+            },
+            k => return Err(format!("unknown signal kind: {}", k)),
+        };
+        Ok(Signal {
+            name: name.to_owned(),
+            kind,
+            amplitude: number("amplitude", 1.0)?,
+            offset: number("offset", 0.0)?,
+        })
+    }
+}
+
+impl Signal {
+    fn random(name: String, rng: &mut impl Rng) -> Self {
+        let period = rng.gen_range(0.5..10.0);
+        let kind = match rng.gen_range(0..6) {
+            0 => Kind::Sine { period },
+            1 => Kind::Square { period },
+            2 => Kind::Noise,
+            3 => Kind::Walk { step: 0.1 },
+            4 => Kind::Steps {
+                values: (0..4).map(|_| rng.gen_range(-1.0..1.0)).collect(),
+                hold: period,
+            },
+            _ => Kind::Bool { period },
+        };
+        Signal {
+            name,
+            kind,
+            amplitude: rng.gen_range(1.0..100.0),
+            offset: rng.gen_range(-100.0..100.0),
+        }
+    }
+
+    /// Value at `t` seconds, `state` keeps the random walk
+    fn sample(&self, t: f32, state: &mut f32, rng: &mut impl Rng) -> f32 {
+        let v = match &self.kind {
+            Kind::Sine { period } => (t / period * TAU).sin(),
+            Kind::Square { period } => {
+                if (t / period).fract() < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Kind::Noise => rng.gen_range(-1.0..1.0),
+            Kind::Walk { step } => {
+                *state += rng.gen_range(-step..*step);
+                *state
+            }
+            Kind::Steps { values, hold } => values[(t / hold) as usize % values.len().max(1)],
+            Kind::Bool { period } => {
+                return ((t / period).fract() < 0.5) as u8 as f32;
+            }
+        };
+        self.offset + self.amplitude * v
+    }
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct Options {
+    /// `name=sine|square|noise|walk|steps|bool[:param=value...]` with
+    /// period, amplitude, offset, step, values (`a/b/c`) and hold
+    #[arg(short, long = "signal")]
+    pub signals: Vec<Signal>,
+    /// Additional keys `sim_<n>` with random signals
+    #[arg(short, long, default_value = "0")]
+    pub keys: usize,
+    /// Samples per second and key
+    #[arg(short, long, default_value = "60")]
+    pub rate: f32,
+    /// Samples per request
+    #[arg(short, long, default_value = "10")]
+    pub batch: usize,
+    /// Concurrent sources, each pushing every key prefixed with `s<n>.`
+    #[arg(long, default_value = "1")]
+    pub sources: usize,
+    /// Push as fast as the server replies instead of pacing at the rate
+    #[arg(long)]
+    pub fast: bool,
+    /// Stop after this many seconds
+    #[arg(short, long)]
+    pub duration: Option<f32>,
+    #[arg(long, default_value = "http://127.0.0.1:8080")]
+    pub server: String,
+    /// Write token of the server
+    #[arg(short, long)]
+    pub token: Option<String>,
+    /// Clients fetching `--download` in a loop meanwhile
+    #[arg(long, default_value = "0")]
+    pub downloaders: usize,
+    /// Paths fetched by the downloaders, taking turns
+    #[arg(long = "download", default_value = "/download.json")]
+    pub downloads: Vec<String>,
+    /// Clients listening on `/socket` meanwhile
+    #[arg(long, default_value = "0")]
+    pub ws_clients: usize,
+    /// Read token of the server for the download and WebSocket clients
+    #[arg(long)]
+    pub read_token: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct Stats {
+    requests: u64,
+    samples: u64,
+    errors: u64,
+    latencies: Vec<Duration>,
+    downloads: u64,
+    messages: u64,
+}
+
+impl Stats {
+    fn report(&mut self, elapsed: Duration) {
+        self.latencies.sort();
+        let percentile = |p: f64| {
+            self.latencies
+                .get(
+                    ((self.latencies.len() as f64 * p) as usize)
+                        .min(self.latencies.len().max(1) - 1),
+                )
+                .map(|d| d.as_secs_f64() * 1000.0)
+                .unwrap_or_default()
+        };
+        let secs = elapsed.as_secs_f64();
+        log::info!(
+            "{:.0} req/s, {:.0} samples/s, {} errors, latency p50 {:.2}ms p99 {:.2}ms max {:.2}ms",
+            self.requests as f64 / secs,
+            self.samples as f64 / secs,
+            self.errors,
+            percentile(0.5),
+            percentile(0.99),
+            percentile(1.0),
+        );
+        if self.downloads > 0 || self.messages > 0 {
+            log::info!(
+                "{:.1} downloads/s, {:.0} WebSocket messages/s",
+                self.downloads as f64 / secs,
+                self.messages as f64 / secs,
+            );
+        }
+    }
+}
+
+pub async fn run(options: &Options) -> Result<(), String> {
+    let mut rng = rand::thread_rng();
+    let mut signals = options.signals.clone();
+    signals.extend((0..options.keys).map(|i| Signal::random(format!("sim_{}", i), &mut rng)));
+    if signals.is_empty() {
+        return Err("no signals given, use --signal or --keys".into());
+    }
+    if options.batch == 0 || options.rate.is_nan() || options.rate <= 0.0 {
+        return Err("batch and rate must be positive".into());
+    }
+    // the interval of the sources
+    Duration::try_from_secs_f32(options.batch as f32 / options.rate)
+        .map_err(|_| "rate is too low".to_owned())?;
+    let duration = options
+        .duration
+        .map(Duration::try_from_secs_f32)
+        .transpose()
+        .map_err(|e| format!("duration: {}", e))?;
+    let signals = Arc::new(signals);
+    let client = hyper::Client::new();
+    let deadline = duration.and_then(|d| Instant::now().checked_add(d));
+
+    let total = Arc::new(Mutex::new(Stats::default()));
+    let window = Arc::new(Mutex::new(Stats::default()));
+    let start = Instant::now();
+    let sources: Vec<_> = (0..options.sources)
+        .map(|i| {
+            let prefix = if options.sources > 1 {
+                format!("s{}.", i)
+            } else {
+                String::new()
+            };
+            tokio::spawn(source(
+                options.clone(),
+                format!("sim{}", i),
+                prefix,
+                signals.clone(),
+                client.clone(),
+                deadline,
+                [total.clone(), window.clone()],
+            ))
+        })
+        .collect();
+    let stats = [total.clone(), window.clone()];
+    let readers: Vec<_> = (0..options.downloaders)
+        .map(|i| {
+            let path = options.downloads[i % options.downloads.len()].clone();
+            tokio::spawn(downloader(
+                options.clone(),
+                path,
+                client.clone(),
+                stats.clone(),
+            ))
+        })
+        .chain(
+            (0..options.ws_clients)
+                .map(|_| tokio::spawn(ws_client(options.clone(), stats.clone()))),
+        )
+        .collect();
+
+    let reporter = {
+        let window = window.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(1));
+            interval.tick().await;
+            loop {
+                let started = Instant::now();
+                interval.tick().await;
+                std::mem::take(&mut *window.lock().unwrap()).report(started.elapsed());
+            }
+        })
+    };
+    for s in sources {
+        s.await.map_err(|e| e.to_string())?;
+    }
+    reporter.abort();
+    readers.iter().for_each(|r| r.abort());
+    log::info!("total");
+    total.lock().unwrap().report(start.elapsed());
+    Ok(())
+}
+
+async fn source(
+    options: Options,
+    name: String,
+    prefix: String,
+    signals: Arc<Vec<Signal>>,
+    client: hyper::Client<hyper::client::HttpConnector>,
+    deadline: Option<Instant>,
+    stats: [Arc<Mutex<Stats>>; 2],
+) {
+    let mut rng = rand::rngs::StdRng::from_entropy();
+    let mut state = vec![0.0; signals.len()];
+    let period = Duration::from_secs_f32(options.batch as f32 / options.rate);
+    let mut interval = tokio::time::interval(period);
+    let mut tick = 0u64;
+    let mut seq = 0u16;
+    let run: u32 = rng.gen_range(1..1 << 30);
+    while deadline.is_none_or(|d| Instant::now() < d) {
+        if !options.fast {
+            interval.tick().await;
+        }
+        let mut batch = HashMap::<String, Vec<f32>>::new();
+        for (i, signal) in signals.iter().enumerate() {
+            let values = (0..options.batch)
+                .map(|n| {
+                    let t = (tick + n as u64) as f32 / options.rate;
+                    signal.sample(t, &mut state[i], &mut rng)
+                })
+                .collect();
+            batch.insert(format!("{}{}", prefix, signal.name), values);
+        }
+        tick += options.batch as u64;
+        seq = seq.wrapping_add(1);
+
+        let payload = match rmp_serde::to_vec(&batch) {
+            Ok(v) => BASE64_URL_SAFE_NO_PAD.encode(v),
+            Err(e) => {
+                log::error!("failed to encode message pack: {}", e);
+                return;
+            }
+        };
+        let mut uri = format!(
+            "{}/p?{}&r={}&s={}&src={}",
+            options.server, payload, run, seq, name
+        );
+        if let Some(token) = &options.token {
+            uri.push('&');
+            uri.push_str(&serde_urlencoded::to_string([("token", token)]).unwrap());
+        }
+        let sent = Instant::now();
+        let ok = match uri.parse() {
+            Ok(uri) => match client.get(uri).await {
+                Ok(res) => match hyper::body::to_bytes(res.into_body()).await {
+                    Ok(body) => body.starts_with(b"OK"),
+                    Err(_) => false,
+                },
+                Err(e) => {
+                    log::warn!("{}", e);
+                    false
+                }
+            },
+            Err(e) => {
+                log::error!("invalid server url: {}", e);
+                return;
+            }
+        };
+        let latency = sent.elapsed();
+        for stats in &stats {
+            let mut stats = stats.lock().unwrap();
+            stats.requests += 1;
+            stats.samples += (options.batch * signals.len()) as u64;
+            stats.errors += !ok as u64;
+            stats.latencies.push(latency);
+        }
+    }
+}
+
+fn read_url(options: &Options, scheme: &str, path: &str) -> String {
+    let server = options
+        .server
+        .split_once("://")
+        .map_or(&*options.server, |(_, s)| s);
+    let mut url = format!("{}://{}{}", scheme, server, path);
+    if let Some(token) = &options.read_token {
+        url.push(if path.contains('?') { '&' } else { '?' });
+        url.push_str(&serde_urlencoded::to_string([("token", token)]).unwrap());
+    }
+    url
+}
+
+async fn downloader(
+    options: Options,
+    path: String,
+    client: hyper::Client<hyper::client::HttpConnector>,
+    stats: [Arc<Mutex<Stats>>; 2],
+) {
+    let uri: hyper::Uri = match read_url(&options, "http", &path).parse() {
+        Ok(uri) => uri,
+        Err(e) => return log::error!("invalid server url: {}", e),
+    };
+    loop {
+        let ok = match client.get(uri.clone()).await {
+            Ok(res) if res.status().is_success() => {
+                hyper::body::to_bytes(res.into_body()).await.is_ok()
+            }
+            _ => false,
+        };
+        if !ok {
+            log::warn!("download of {} failed", path);
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            continue;
+        }
+        for stats in &stats {
+            stats.lock().unwrap().downloads += 1;
+        }
+    }
+}
+
+async fn ws_client(options: Options, stats: [Arc<Mutex<Stats>>; 2]) {
+    use futures::StreamExt;
+    let url = read_url(&options, "ws", "/socket");
+    let mut socket = match tokio_tungstenite::connect_async(&url).await {
+        Ok((socket, _)) => socket,
+        Err(e) => return log::error!("failed to connect to {}: {}", url, e),
+    };
+    while let Some(Ok(_)) = socket.next().await {
+        for stats in &stats {
+            stats.lock().unwrap().messages += 1;
+        }
+    }
+    log::warn!("WebSocket closed");
+}
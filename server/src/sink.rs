@@ -0,0 +1,188 @@
+//! Outputs every ingested batch is dispatched to, enabled with `--sink`.
+use crate::{args::Args, db::Db, mqtt, relay, values::Values};
+use axum::extract::ws::Message;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::broadcast;
+
+/// Ingested samples of a push
+pub struct Batch<'a> {
+    /// as pushed, lost samples filled with NaN
+    pub live: &'a HashMap<String, Vec<f32>>,
+    /// what the recorder lets through
+    pub recorded: &'a HashMap<String, Vec<f32>>,
+}
+
+pub trait Sink: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn write(&self, batch: &Batch) -> Result<(), String>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Kind {
+    /// buffers of the viewer downloads and the database of --db
+    Store,
+    /// JSON messages to /socket and /events clients
+    Broadcast,
+    /// JSON Lines of the recorded samples in --sink-file
+    File,
+    /// latest values to the MQTT broker of --mqtt
+    Mqtt,
+    /// every batch to the server of --upstream
+    Relay,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+#[group(id = "sink")]
+pub struct Options {
+    /// Outputs of ingested batches
+    #[arg(
+        long = "sink",
+        value_delimiter = ',',
+        default_value = "store,broadcast"
+    )]
+    pub sinks: Vec<Kind>,
+    /// File appended to by the file sink
+    #[arg(long)]
+    pub sink_file: Option<PathBuf>,
+}
+
+/// Ring buffers and the optional database, samples are stored at the tick
+/// the buffers are at
+pub struct Store {
+    pub values: Arc<Values>,
+    pub db: Option<Arc<Db>>,
+}
+
+impl Sink for Store {
+    fn name(&self) -> &'static str {
+        "store"
+    }
+
+    fn write(&self, batch: &Batch) -> Result<(), String> {
+        // keys appearing in this batch start where the others are
+        let start = self.values.tick();
+        for (key, values) in batch.recorded {
+            let tick = self.values.push(key.clone(), values, start);
+            if let Some(db) = &self.db {
+                db.push(key, tick, values);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Sends the live samples whether recording or not
+pub struct Broadcast {
+    pub tx: broadcast::Sender<Message>,
+}
+
+impl Sink for Broadcast {
+    fn name(&self) -> &'static str {
+        "broadcast"
+    }
+
+    fn write(&self, batch: &Batch) -> Result<(), String> {
+        let json = serde_json::to_string(batch.live).map_err(|e| e.to_string())?;
+        self.tx.send(Message::Text(json)).ok();
+        Ok(())
+    }
+}
+
+/// A `{"time": <unix ms>, "values": {...}}` line per recorded batch,
+/// written by a thread which flushes everything queued at once
+pub struct FileSink {
+    tx: mpsc::Sender<String>,
+}
+
+impl FileSink {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        let (tx, rx) = mpsc::channel();
+        let path = path.to_owned();
+        std::thread::spawn(move || write_lines(BufWriter::new(file), &path, rx));
+        Ok(Self { tx })
+    }
+}
+
+/// Writes the lines of `rx` to `path` until all senders are dropped
+pub fn write_lines(mut writer: BufWriter<File>, path: &Path, rx: mpsc::Receiver<String>) {
+    while let Ok(first) = rx.recv() {
+        let result = std::iter::once(first)
+            .chain(rx.try_iter())
+            .try_for_each(|line| writeln!(writer, "{}", line))
+            .and_then(|()| writer.flush());
+        if let Err(e) = result {
+            log::error!("failed to write {}: {}", path.display(), e);
+        }
+    }
+}
+
+impl Sink for FileSink {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    fn write(&self, batch: &Batch) -> Result<(), String> {
+        if batch.recorded.is_empty() {
+            return Ok(());
+        }
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let line = serde_json::json!({ "time": time, "values": batch.recorded });
+        self.tx
+            .send(line.to_string())
+            .map_err(|_| "file writer stopped".into())
+    }
+}
+
+/// Rejects a `--db` nothing would be written to
+pub fn check(options: &Options, db: bool) -> Result<(), String> {
+    if db && !options.sinks.contains(&Kind::Store) {
+        return Err("--db is only written by the store sink, add it to --sink".into());
+    }
+    Ok(())
+}
+
+/// Sinks of `--sink` in the given order
+pub fn build(
+    args: &Args,
+    values: &Arc<Values>,
+    db: &Option<Arc<Db>>,
+    tx: &broadcast::Sender<Message>,
+    commands: &tokio::sync::mpsc::Sender<String>,
+) -> Result<Vec<Box<dyn Sink>>, String> {
+    let options = &args.sinks;
+    let mut sinks: Vec<Box<dyn Sink>> = vec![];
+    for kind in &options.sinks {
+        sinks.push(match kind {
+            Kind::Store => Box::new(Store {
+                values: values.clone(),
+                db: db.clone(),
+            }),
+            Kind::Broadcast => Box::new(Broadcast { tx: tx.clone() }),
+            Kind::File => {
+                let path = options
+                    .sink_file
+                    .as_ref()
+                    .ok_or("the file sink needs --sink-file")?;
+                Box::new(FileSink::open(path)?)
+            }
+            Kind::Mqtt => Box::new(mqtt::Mqtt::connect(&args.mqtt, commands.clone())?),
+            Kind::Relay => Box::new(relay::Relay::start(&args.relay)?),
+        });
+    }
+    Ok(sinks)
+}
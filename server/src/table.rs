@@ -0,0 +1,136 @@
+//! Keys side by side with a row per tick, as exported by `/download.csv`,
+//! `/download.jsonl` and `/download.parquet`.
+use crate::values::Buffer;
+use std::{collections::HashMap, ops::Range};
+
+const TICKS_PER_SECOND: f64 = 60.0;
+
+pub struct Table {
+    keys: Vec<String>,
+    buffers: Vec<Buffer>,
+    /// unix time in seconds of tick 0
+    started: u64,
+    ticks: Range<u64>,
+}
+
+impl Table {
+    /// `keys` of `buffers` in that order, otherwise all of them sorted by name
+    pub fn new(
+        mut buffers: HashMap<String, Buffer>,
+        keys: Option<Vec<String>>,
+        started: u64,
+    ) -> Self {
+        let keys = keys.unwrap_or_else(|| {
+            let mut keys: Vec<_> = buffers.keys().cloned().collect();
+            keys.sort_unstable();
+            keys
+        });
+        // a key given twice only gets its buffer once
+        let (keys, buffers): (Vec<_>, Vec<_>) = keys
+            .into_iter()
+            .filter_map(|k| buffers.remove(&k).map(|b| (k, b)))
+            .unzip();
+        let start = buffers.iter().map(|b| b.start).min().unwrap_or_default();
+        let end = buffers
+            .iter()
+            .map(|b| b.start + b.values.len() as u64)
+            .max()
+            .unwrap_or_default();
+        Self {
+            keys,
+            buffers,
+            started,
+            ticks: start..end,
+        }
+    }
+
+    /// Columns of `keys` over `ticks`, for reading a session in blocks
+    pub fn block(keys: Vec<String>, buffers: Vec<Buffer>, started: u64, ticks: Range<u64>) -> Self {
+        Self {
+            keys,
+            buffers,
+            started,
+            ticks,
+        }
+    }
+
+    /// Keeps the ticks from `from` up to but excluding `to`
+    pub fn limit(mut self, from: Option<u64>, to: Option<u64>) -> Self {
+        if let Some(from) = from {
+            self.ticks.start = self.ticks.start.max(from);
+        }
+        if let Some(to) = to {
+            self.ticks.end = self.ticks.end.min(to);
+        }
+        self.ticks.end = self.ticks.end.max(self.ticks.start);
+        self
+    }
+
+    pub fn keys(&self) -> &[String] {
+        &self.keys
+    }
+
+    pub fn ticks(&self) -> Range<u64> {
+        self.ticks.clone()
+    }
+
+    /// `None` before the first or after the last sample of a key and for
+    /// lost samples
+    pub fn value(&self, column: usize, tick: u64) -> Option<f32> {
+        let buffer = &self.buffers[column];
+        let index = tick.checked_sub(buffer.start)?;
+        buffer
+            .values
+            .get(index as usize)
+            .copied()
+            .filter(|v| !v.is_nan())
+    }
+
+    /// Unix time in seconds, estimated from the session start at 60 ticks
+    /// per second
+    pub fn time(&self, tick: u64) -> f64 {
+        self.started as f64 + tick as f64 / TICKS_PER_SECOND
+    }
+
+    pub fn csv_header(&self) -> String {
+        let mut line = String::from("tick,time");
+        for key in &self.keys {
+            line.push(',');
+            line += &csv_field(key);
+        }
+        line.push('\n');
+        line
+    }
+
+    pub fn csv_row(&self, tick: u64) -> String {
+        let mut line = format!("{},{:.3}", tick, self.time(tick));
+        for column in 0..self.keys.len() {
+            line.push(',');
+            if let Some(v) = self.value(column, tick) {
+                line += &v.to_string();
+            }
+        }
+        line.push('\n');
+        line
+    }
+
+    pub fn jsonl_row(&self, tick: u64) -> String {
+        let mut row = serde_json::Map::new();
+        row.insert("tick".into(), tick.into());
+        row.insert("time".into(), self.time(tick).into());
+        for (column, key) in self.keys.iter().enumerate() {
+            row.insert(key.clone(), self.value(column, tick).into());
+        }
+        let mut line = serde_json::Value::Object(row).to_string();
+        line.push('\n');
+        line
+    }
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
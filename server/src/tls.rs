@@ -0,0 +1,27 @@
+use crate::args::Args;
+use axum_server::tls_rustls::RustlsConfig;
+
+pub async fn config(args: &Args) -> std::io::Result<RustlsConfig> {
+    match (&args.tls_cert, &args.tls_key) {
+        (Some(cert), Some(key)) => RustlsConfig::from_pem_file(cert, key).await,
+        (None, None) => {
+            let mut names = vec!["localhost".to_owned()];
+            if !args.tls_ip.is_unspecified() {
+                names.push(args.tls_ip.to_string());
+            }
+            names.extend(args.tls_name.iter().cloned());
+            log::info!(
+                "generating self-signed certificate for {}",
+                names.join(", ")
+            );
+            let cert = rcgen::generate_simple_self_signed(names)
+                .and_then(|c| Ok((c.serialize_pem()?, c.serialize_private_key_pem())))
+                .map_err(std::io::Error::other)?;
+            RustlsConfig::from_pem(cert.0.into_bytes(), cert.1.into_bytes()).await
+        }
+        _ => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "--tls-cert and --tls-key must be given together",
+        )),
+    }
+}
@@ -1,11 +1,65 @@
+use crate::rollup::{History, Resolution, Tier};
 use serde::Serialize;
-use std::collections::{HashMap, VecDeque};
+use std::{
+    collections::{hash_map::RandomState, HashMap, VecDeque},
+    hash::BuildHasher,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        RwLock,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-#[derive(Debug, Serialize)]
+const SHARDS: usize = 16;
+
+#[derive(Debug)]
+struct Series {
+    samples: VecDeque<f32>,
+    /// tick after the last sample
+    pushed: u64,
+    /// unix time in milliseconds of the last push
+    updated: u64,
+    seconds: Tier,
+    minutes: Tier,
+}
+
+/// Ring buffers and rollup tiers per key, spread over shards with their own lock.
+///
+/// A push only blocks readers and writers of keys in the same shard, and
+/// readers copy one shard at a time so serializing a download never holds a
+/// lock.
+#[derive(Debug)]
 pub struct Values {
-    values: HashMap<String, VecDeque<f32>>,
-    #[serde(skip)]
+    shards: Vec<RwLock<HashMap<String, Series>>>,
+    hasher: RandomState,
     max_len: usize,
+    tick: AtomicU64,
+}
+
+/// Entry of `/keys`
+#[derive(Debug, Serialize)]
+pub struct Key {
+    pub name: String,
+    /// samples in the buffer
+    pub samples: usize,
+    /// tick after the last sample of the session
+    pub pushed: u64,
+    pub last: Option<f32>,
+    /// unix time in milliseconds of the last push
+    pub updated: u64,
+}
+
+/// Buffer of a single key, `start` is the tick of the first sample
+#[derive(Debug, Serialize)]
+pub struct Buffer {
+    pub start: u64,
+    pub values: Vec<f32>,
+}
+
+/// Copy of all buffers, serialized as the `/download.json` dump
+#[derive(Debug, Default, Serialize)]
+pub struct Snapshot {
+    pub values: HashMap<String, Vec<f32>>,
 }
 
 impl Default for Values {
@@ -17,20 +71,142 @@ impl Default for Values {
 impl Values {
     pub fn with_capacity(max_len: usize) -> Self {
         Self {
-            values: Default::default(),
+            shards: (0..SHARDS).map(|_| Default::default()).collect(),
+            hasher: RandomState::new(),
             max_len,
+            tick: AtomicU64::new(0),
         }
     }
 
-    pub fn push(&mut self, key: String, values: &[f32]) {
-        let vec = self
-            .values
-            .entry(key)
-            .or_insert_with(|| VecDeque::with_capacity(self.max_len));
+    /// Samples kept per key
+    pub fn capacity(&self) -> usize {
+        self.max_len
+    }
+
+    /// Tick after the last sample of the furthest key
+    pub fn tick(&self) -> u64 {
+        self.tick.load(Ordering::Relaxed)
+    }
+
+    fn shard(&self, key: &str) -> &RwLock<HashMap<String, Series>> {
+        &self.shards[self.hasher.hash_one(key) as usize % SHARDS]
+    }
+
+    /// Returns the tick of the first of `values`, a new key starts at `start`,
+    /// the tick of the batch it came with
+    pub fn push(&self, key: String, values: &[f32], start: u64) -> u64 {
+        let mut shard = self.shard(&key).write().unwrap();
+        let series = shard.entry(key).or_insert_with(|| Series {
+            samples: VecDeque::with_capacity(self.max_len),
+            pushed: start,
+            updated: 0,
+            seconds: Tier::seconds(start),
+            minutes: Tier::minutes(start),
+        });
+        let tick = series.pushed;
+        series.pushed += values.len() as u64;
+        series.updated = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        series.seconds.push(values);
+        series.minutes.push(values);
+        self.tick.fetch_max(series.pushed, Ordering::Relaxed);
 
+        let vec = &mut series.samples;
         if vec.len() + values.len() > self.max_len {
-            vec.drain(0..(vec.len() + values.len() - self.max_len));
+            vec.drain(0..(vec.len() + values.len() - self.max_len).min(vec.len()));
+        }
+        let skip = values.len().saturating_sub(self.max_len);
+        vec.extend(&values[skip..]);
+        tick
+    }
+
+    /// Moves all buffers into a new instance, restarting at tick 0
+    pub fn take(&self) -> Values {
+        Values {
+            shards: self
+                .shards
+                .iter()
+                .map(|shard| RwLock::new(std::mem::take(&mut *shard.write().unwrap())))
+                .collect(),
+            // the keys stay in their shards
+            hasher: self.hasher.clone(),
+            max_len: self.max_len,
+            tick: AtomicU64::new(self.tick.swap(0, Ordering::Relaxed)),
+        }
+    }
+
+    pub fn history(&self, key: &str, resolution: Resolution) -> Option<History> {
+        let shard = self.shard(key).read().unwrap();
+        let series = shard.get(key)?;
+        Some(match resolution {
+            Resolution::Raw => {
+                let samples: Vec<_> = series.samples.iter().copied().collect();
+                History {
+                    step: 1,
+                    start: series.pushed - samples.len() as u64,
+                    min: samples.clone(),
+                    max: samples.clone(),
+                    mean: samples,
+                }
+            }
+            Resolution::Second => series.seconds.history(),
+            Resolution::Minute => series.minutes.history(),
+        })
+    }
+
+    /// All keys sorted by name
+    pub fn keys(&self) -> Vec<Key> {
+        let mut keys = vec![];
+        for shard in &self.shards {
+            let shard = shard.read().unwrap();
+            keys.extend(shard.iter().map(|(name, s)| Key {
+                name: name.clone(),
+                samples: s.samples.len(),
+                pushed: s.pushed,
+                last: s.samples.back().copied(),
+                updated: s.updated,
+            }));
+        }
+        keys.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+        keys
+    }
+
+    pub fn buffer(&self, key: &str) -> Option<Buffer> {
+        let shard = self.shard(key).read().unwrap();
+        let series = shard.get(key)?;
+        Some(Buffer {
+            start: series.pushed - series.samples.len() as u64,
+            values: series.samples.iter().copied().collect(),
+        })
+    }
+
+    pub fn buffers(&self) -> HashMap<String, Buffer> {
+        let mut buffers = HashMap::new();
+        for shard in &self.shards {
+            let shard = shard.read().unwrap();
+            buffers.extend(shard.iter().map(|(k, s)| {
+                let buffer = Buffer {
+                    start: s.pushed - s.samples.len() as u64,
+                    values: s.samples.iter().copied().collect(),
+                };
+                (k.clone(), buffer)
+            }));
+        }
+        buffers
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        let mut snapshot = Snapshot::default();
+        for shard in &self.shards {
+            let shard = shard.read().unwrap();
+            snapshot.values.extend(
+                shard
+                    .iter()
+                    .map(|(k, s)| (k.clone(), s.samples.iter().copied().collect())),
+            );
         }
-        vec.extend(values)
+        snapshot
     }
 }
@@ -1,5 +1,8 @@
 use crate::{
     graph::{LineGraph, XYGraph},
+    history::History,
+    recording,
+    sessions::Sessions,
     table::TableWindow,
     values::Values,
 };
@@ -16,25 +19,75 @@ pub enum Window {
 }
 
 impl Window {
-    fn show(&mut self, ctx: &Context, open: &mut bool, values: &Values) {
+    fn show(&mut self, ctx: &Context, open: &mut bool, values: &Values, history: &History) {
         match self {
-            Window::LineGraph(w) => w.show(ctx, open, values),
+            Window::LineGraph(w) => w.show(ctx, open, values, history),
             Window::XYGraph(w) => w.show(ctx, open, values),
             Window::Table(w) => w.show(ctx, open, values),
         }
     }
 }
 
+#[derive(Default, Deserialize)]
+struct SourceStats {
+    received: u64,
+    lost: u64,
+    duplicated: u64,
+    reordered: u64,
+}
+
+#[derive(Default, Deserialize)]
+struct Status {
+    sources: HashMap<String, SourceStats>,
+    #[serde(default)]
+    session: i64,
+    #[serde(default)]
+    recording: recording::Status,
+}
+
+/// Fired or cleared alert rule of the server
+#[derive(Deserialize)]
+struct Alert {
+    rule: String,
+    key: String,
+    value: Option<f32>,
+    active: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ServerMessage {
+    Status { status: Status },
+    Alert { alert: Alert },
+    Values(HashMap<String, Vec<Option<f32>>>),
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct App {
     id: usize,
     server: String,
+    #[serde(default)]
+    token: String,
     #[serde(skip, default)]
     ws: Option<(WsSender, WsReceiver)>,
+    /// URL of the socket including the token
+    #[serde(skip, default)]
+    socket: Option<url::Url>,
+    #[serde(default)]
+    recording: recording::Controls,
     values: Values,
     windows: Vec<(Window, bool)>,
     #[serde(skip, default)]
     save_dialog: Option<FileDialog>,
+    #[serde(skip, default)]
+    status: Status,
+    #[serde(skip, default)]
+    history: History,
+    #[serde(skip, default)]
+    sessions: Sessions,
+    /// currently firing alerts
+    #[serde(skip, default)]
+    alerts: Vec<Alert>,
 }
 
 impl App {
@@ -47,17 +100,29 @@ impl App {
         #[cfg(target_arch = "wasm32")]
         let server = {
             let location = &cc.integration_info.web_info.location;
-            format!("ws://{}/socket", location.host)
+            let scheme = if location.protocol == "https:" {
+                "wss"
+            } else {
+                "ws"
+            };
+            format!("{}://{}/socket", scheme, location.host)
         };
         #[cfg(not(target_arch = "wasm32"))]
         let server = "ws://127.0.0.1:8080/socket".into();
         Self {
             id: 0,
             server,
+            token: String::new(),
             ws: None,
+            socket: None,
+            recording: Default::default(),
             values: Default::default(),
             windows: vec![],
             save_dialog: None,
+            status: Default::default(),
+            history: Default::default(),
+            sessions: Default::default(),
+            alerts: vec![],
         }
     }
 }
@@ -73,10 +138,27 @@ impl eframe::App for App {
                 match e {
                     ewebsock::WsEvent::Opened => {}
                     ewebsock::WsEvent::Message(WsMessage::Text(m)) => {
-                        match serde_json::from_str::<HashMap<String, Vec<f32>>>(&m) {
-                            Ok(v) => {
+                        match serde_json::from_str::<ServerMessage>(&m) {
+                            Ok(ServerMessage::Status { status }) => {
+                                if self.status.session != 0 && status.session != self.status.session
+                                {
+                                    // the server restarted at tick 0
+                                    self.values = Values::with_capacity(self.values.max_len());
+                                    self.sessions.refresh(ctx);
+                                }
+                                self.status = status;
+                            }
+                            Ok(ServerMessage::Alert { alert }) => {
+                                self.alerts.retain(|a| a.rule != alert.rule);
+                                if alert.active {
+                                    self.alerts.push(alert);
+                                }
+                            }
+                            Ok(ServerMessage::Values(v)) => {
                                 for (k, v) in v {
-                                    self.values.push(k, v);
+                                    // gaps of lost batches arrive as null
+                                    let v = v.into_iter().map(|v| v.unwrap_or(f32::NAN));
+                                    self.values.push(k, v.collect());
                                 }
                             }
                             Err(e) => {
@@ -87,11 +169,7 @@ impl eframe::App for App {
                     ewebsock::WsEvent::Message(_) => {}
                     ewebsock::WsEvent::Error(e) => log::error!("{}", e),
                     ewebsock::WsEvent::Closed => {
-                        let ctx = ctx.clone();
-                        let wakeup = move || ctx.request_repaint();
-                        self.ws = ewebsock::connect_with_wakeup(&self.server, wakeup)
-                            .map_err(|e| log::error!("failed to init websocket {}", e))
-                            .ok();
+                        self.connect(ctx);
                         break;
                     }
                 }
@@ -148,25 +226,50 @@ impl eframe::App for App {
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.horizontal(|ui| {
                 ui.text_edit_singleline(&mut self.server);
+                ui.label("token");
+                ui.add(egui::TextEdit::singleline(&mut self.token).password(true));
                 if self.ws.is_none() {
                     if ui.button("connect").clicked() {
-                        let ctx = ctx.clone();
-                        let wakeup = move || ctx.request_repaint();
-                        self.ws = ewebsock::connect_with_wakeup(&self.server, wakeup)
-                            .map_err(|e| log::error!("failed to init websocket {}", e))
-                            .ok();
+                        self.connect(ctx);
                     }
                 } else if ui.button("disconnect").clicked() {
                     self.ws = None;
                 }
+                if self.sessions.ui(ui, self.status.session) {
+                    self.history.set_session(self.sessions.selected());
+                }
             });
+            self.recording
+                .ui(ui, &self.status.recording, self.socket.as_ref());
+            for (source, stats) in &self.status.sources {
+                let sent = stats.received + stats.lost;
+                ui.label(format!(
+                    "{}: received {}, lost {} ({:.1}%), duplicated {}, reordered {}",
+                    if source.is_empty() { "default" } else { source },
+                    stats.received,
+                    stats.lost,
+                    stats.lost as f64 * 100.0 / sent.max(1) as f64,
+                    stats.duplicated,
+                    stats.reordered,
+                ));
+            }
+            for alert in &self.alerts {
+                let value = alert.value.map_or("-".into(), |v| v.to_string());
+                ui.colored_label(
+                    ui.visuals().error_fg_color,
+                    format!("alert {}: {} = {}", alert.rule, alert.key, value),
+                );
+            }
             ui.separator();
             self.table(ui);
         });
 
+        let past = self.sessions.values();
+        let values = past.as_ref().unwrap_or(&self.values);
         for graph in &mut self.windows {
-            graph.0.show(ctx, &mut graph.1, &self.values);
+            graph.0.show(ctx, &mut graph.1, values, &self.history);
         }
+        drop(past);
         self.windows.retain(|g| g.1);
 
         if let Some(save_dialog) = self.save_dialog.as_mut() {
@@ -181,6 +284,29 @@ impl eframe::App for App {
 }
 
 impl App {
+    fn connect(&mut self, ctx: &Context) {
+        let mut url = match url::Url::parse(&self.server) {
+            Ok(url) => url,
+            Err(e) => {
+                log::error!("invalid server url {}", e);
+                self.ws = None;
+                return;
+            }
+        };
+        if !self.token.is_empty() {
+            url.query_pairs_mut().append_pair("token", &self.token);
+        }
+        self.socket = Some(url.clone());
+        self.history = History::new(&url);
+        self.sessions = Sessions::new(&url);
+        self.sessions.refresh(ctx);
+        let ctx = ctx.clone();
+        let wakeup = move || ctx.request_repaint();
+        self.ws = ewebsock::connect_with_wakeup(url.as_str(), wakeup)
+            .map_err(|e| log::error!("failed to init websocket {}", e))
+            .ok();
+    }
+
     fn table(&mut self, ui: &mut egui::Ui) {
         let mut keys: Vec<_> = self.values.keys().collect();
         keys.sort();
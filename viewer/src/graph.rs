@@ -1,6 +1,9 @@
-use crate::values::Values;
+use crate::{
+    history::{History, Resolution},
+    values::Values,
+};
 use egui::{vec2, Context, Id, ScrollArea, Ui};
-use egui_plot::{Legend, Line, Plot, PlotPoints};
+use egui_plot::{Legend, Line, LineStyle, Plot, PlotPoints};
 use serde::{Deserialize, Serialize};
 use std::hash::Hash;
 
@@ -62,6 +65,9 @@ pub struct LineGraph {
     x_axis_position: VPlacement,
     y_axis_position: HPlacement,
     period: usize,
+    /// plot the rollups of the server instead of the received samples
+    #[serde(default)]
+    history: Option<Resolution>,
 }
 
 impl LineGraph {
@@ -75,19 +81,20 @@ impl LineGraph {
             x_axis_position: VPlacement::Bottom,
             y_axis_position: HPlacement::Right,
             period: 3600,
+            history: None,
         }
     }
 
-    pub fn show(&mut self, ctx: &Context, open: &mut bool, values: &Values) {
+    pub fn show(&mut self, ctx: &Context, open: &mut bool, values: &Values, history: &History) {
         egui::Window::new(&self.title)
             .id(self.id)
             .default_size(vec2(400.0, 600.0))
             .vscroll(false)
             .open(open)
-            .show(ctx, |ui| self.ui(ui, values));
+            .show(ctx, |ui| self.ui(ui, values, history));
     }
 
-    pub fn ui(&mut self, ui: &mut Ui, values: &Values) {
+    pub fn ui(&mut self, ui: &mut Ui, values: &Values, history: &History) {
         ScrollArea::horizontal()
             .id_source(self.id.with("header"))
             .show(ui, |ui| {
@@ -114,7 +121,11 @@ impl LineGraph {
             .show_grid(true)
             .show(ui, |ui| {
                 for k in &self.keys {
-                    if let Some(iter) = values.iter_for_key(k) {
+                    if let Some(resolution) = self.history {
+                        if let Some(points) = history.get(ui.ctx(), k, resolution) {
+                            history_lines(ui, k, &points);
+                        }
+                    } else if let Some(iter) = values.iter_for_key(k) {
                         let skip = iter.len().saturating_sub(self.period);
                         let iter = iter.skip(skip);
                         let len = iter.len();
@@ -135,11 +146,48 @@ impl LineGraph {
                     &mut self.x_axis_position,
                     &mut self.y_axis_position,
                     &mut self.period,
-                )
+                );
+                ui.menu_button("History", |ui| {
+                    let mut clicked = false;
+                    for (label, resolution) in [
+                        ("Live", None),
+                        ("1sec resolution (4h)", Some(Resolution::Second)),
+                        ("1min resolution (3d)", Some(Resolution::Minute)),
+                    ] {
+                        clicked |= ui
+                            .radio_value(&mut self.history, resolution, label)
+                            .clicked();
+                    }
+                    if clicked {
+                        ui.close_menu();
+                    }
+                });
             });
     }
 }
 
+/// Mean with dashed min and max, in seconds before the latest point
+fn history_lines(ui: &mut egui_plot::PlotUi, key: &str, points: &crate::history::Points) {
+    let end = points.start + points.mean.len() as u64 * points.step;
+    let line = |values: &[Option<f32>]| {
+        PlotPoints::from_iter(values.iter().enumerate().map(|(n, v)| {
+            let tick = points.start + n as u64 * points.step;
+            [
+                (tick as f64 - end as f64) / 60.0,
+                v.map_or(f64::NAN, |v| v as f64),
+            ]
+        }))
+    };
+    ui.line(Line::new(line(&points.mean)).name(key));
+    for (name, values) in [("min", &points.min), ("max", &points.max)] {
+        ui.line(
+            Line::new(line(values))
+                .style(LineStyle::dashed_dense())
+                .name(format!("{} {}", key, name)),
+        );
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct XYGraph {
     id: Id,
@@ -0,0 +1,97 @@
+//! Rollups of the server (`/history/:key`) for periods beyond the local buffers.
+use crate::request;
+use egui::{ahash::HashMap, Context};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+
+/// Seconds between refreshes of a shown key
+const REFRESH_INTERVAL: f64 = 5.0;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Resolution {
+    Second,
+    Minute,
+}
+
+impl Resolution {
+    fn param(self) -> &'static str {
+        match self {
+            Resolution::Second => "1s",
+            Resolution::Minute => "1m",
+        }
+    }
+}
+
+/// Point `n` covers the ticks from `start + n * step`
+#[derive(Deserialize)]
+pub struct Points {
+    pub step: u64,
+    pub start: u64,
+    pub min: Vec<Option<f32>>,
+    pub max: Vec<Option<f32>>,
+    pub mean: Vec<Option<f32>>,
+}
+
+#[derive(Default)]
+struct Entry {
+    points: Option<Arc<Points>>,
+    requested: Option<f64>,
+}
+
+#[derive(Default)]
+pub struct History {
+    /// the socket URL including the token
+    socket: Option<url::Url>,
+    /// past session instead of the current one
+    session: Option<i64>,
+    entries: Arc<Mutex<HashMap<(String, Resolution), Entry>>>,
+}
+
+impl History {
+    pub fn new(socket: &url::Url) -> Self {
+        Self {
+            socket: Some(socket.clone()),
+            ..Default::default()
+        }
+    }
+
+    pub fn set_session(&mut self, session: Option<i64>) {
+        self.session = session;
+        self.entries.lock().unwrap().clear();
+    }
+
+    /// Last fetched points, requesting them again when outdated.
+    pub fn get(&self, ctx: &Context, key: &str, resolution: Resolution) -> Option<Arc<Points>> {
+        let now = ctx.input(|i| i.time);
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.entry((key.to_owned(), resolution)).or_default();
+        if entry.requested.is_none_or(|t| now - t > REFRESH_INTERVAL) {
+            entry.requested = Some(now);
+            self.fetch(ctx, key, resolution);
+        }
+        entry.points.clone()
+    }
+
+    fn fetch(&self, ctx: &Context, key: &str, resolution: Resolution) {
+        let Some(socket) = &self.socket else {
+            return;
+        };
+        let mut url = request::route(socket, &["history", key]);
+        url.query_pairs_mut().append_pair("res", resolution.param());
+        if let Some(session) = self.session {
+            url.query_pairs_mut()
+                .append_pair("session", &session.to_string());
+        }
+
+        let entries = self.entries.clone();
+        let key = key.to_owned();
+        request::get_json(url, ctx, move |points| match points {
+            Ok(points) => {
+                if let Some(entry) = entries.lock().unwrap().get_mut(&(key, resolution)) {
+                    entry.points = Some(Arc::new(points));
+                }
+            }
+            Err(e) => log::error!("failed to fetch history of {}: {}", key, e),
+        });
+    }
+}
@@ -2,6 +2,10 @@
 
 mod app;
 mod graph;
+mod history;
+mod recording;
+mod request;
+mod sessions;
 mod table;
 mod values;
 
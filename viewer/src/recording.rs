@@ -0,0 +1,96 @@
+//! Controls of the recorder of the server (`/recording/...`).
+use crate::request;
+use serde::{Deserialize, Serialize};
+
+/// `recording` of the server status
+#[derive(Default, Deserialize)]
+pub struct Status {
+    state: String,
+    trigger: Option<String>,
+    duration: Option<u64>,
+    recorded: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct Controls {
+    trigger: String,
+    /// seconds
+    pre_trigger: f32,
+    /// seconds, 0 to record until stopped
+    duration: f32,
+}
+
+impl Default for Controls {
+    fn default() -> Self {
+        Self {
+            trigger: String::new(),
+            pre_trigger: 2.0,
+            duration: 0.0,
+        }
+    }
+}
+
+impl Controls {
+    pub fn ui(&mut self, ui: &mut egui::Ui, status: &Status, socket: Option<&url::Url>) {
+        ui.horizontal(|ui| {
+            let mut text = format!("recorder {}", status.state);
+            if status.state == "armed" {
+                text += &format!(" ({})", status.trigger.as_deref().unwrap_or_default());
+            }
+            if status.state == "recording" {
+                text += &format!(" {:.1}s", status.recorded as f32 / 60.0);
+                if let Some(duration) = status.duration {
+                    text += &format!(" of {:.1}s", duration as f32 / 60.0);
+                }
+            }
+            ui.label(text);
+            ui.separator();
+            ui.label("duration");
+            ui.add(
+                egui::DragValue::new(&mut self.duration)
+                    .clamp_range(0.0..=3600.0)
+                    .suffix("s"),
+            );
+            ui.label("trigger");
+            ui.add(
+                egui::TextEdit::singleline(&mut self.trigger)
+                    .hint_text("key>value")
+                    .desired_width(100.0),
+            );
+            ui.label("pre-trigger");
+            ui.add(
+                egui::DragValue::new(&mut self.pre_trigger)
+                    .clamp_range(0.0..=60.0)
+                    .suffix("s"),
+            );
+            let Some(socket) = socket else {
+                return;
+            };
+            let route = |command: &str| {
+                let mut url = request::route(socket, &["recording", command]);
+                if self.duration > 0.0 {
+                    url.query_pairs_mut()
+                        .append_pair("duration", &self.duration.to_string());
+                }
+                url
+            };
+            if ui.button("Start").clicked() {
+                request::command(route("start"), ui.ctx());
+            }
+            if ui
+                .add_enabled(!self.trigger.is_empty(), egui::Button::new("Arm"))
+                .clicked()
+            {
+                let mut url = route("arm");
+                url.query_pairs_mut()
+                    .append_pair("trigger", &self.trigger)
+                    .append_pair("pre", &self.pre_trigger.to_string());
+                request::command(url, ui.ctx());
+            }
+            if ui.button("Stop").clicked() {
+                request::command(request::route(socket, &["recording", "stop"]), ui.ctx());
+            }
+        });
+    }
+}
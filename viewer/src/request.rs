@@ -0,0 +1,52 @@
+//! JSON requests to the HTTP routes served next to `/socket`.
+use egui::Context;
+use serde::de::DeserializeOwned;
+
+/// `path` relative to the server of the socket URL, keeping its token
+pub fn route(socket: &url::Url, path: &[&str]) -> url::Url {
+    let mut url = socket.clone();
+    let scheme = if url.scheme() == "wss" {
+        "https"
+    } else {
+        "http"
+    };
+    if url.set_scheme(scheme).is_err() {
+        log::error!("unsupported server url {}", socket);
+    }
+    if let Ok(mut segments) = url.path_segments_mut() {
+        segments.pop().extend(path);
+    }
+    url
+}
+
+/// Calls `done` with the parsed response and repaints
+pub fn get_json<T: DeserializeOwned>(
+    url: url::Url,
+    ctx: &Context,
+    done: impl 'static + Send + FnOnce(Result<T, String>),
+) {
+    let ctx = ctx.clone();
+    ehttp::fetch(ehttp::Request::get(url), move |response| {
+        done(response.and_then(|r| {
+            if r.ok {
+                serde_json::from_slice(&r.bytes).map_err(|e| e.to_string())
+            } else {
+                Err(format!("{} {}", r.status, r.text().unwrap_or_default()))
+            }
+        }));
+        ctx.request_repaint();
+    });
+}
+
+/// Sends a command route, logging replies other than `OK`
+pub fn command(url: url::Url, ctx: &Context) {
+    let ctx = ctx.clone();
+    ehttp::fetch(ehttp::Request::get(url), move |response| {
+        match response.map(|r| r.text().unwrap_or_default().to_owned()) {
+            Ok(text) if text.starts_with("OK") => {}
+            Ok(text) => log::error!("{}", text),
+            Err(e) => log::error!("{}", e),
+        }
+        ctx.request_repaint();
+    });
+}
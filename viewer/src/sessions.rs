@@ -0,0 +1,128 @@
+//! Sessions of the server (`/sessions`) and the samples of a past one.
+use crate::{request, values::Values};
+use egui::{ahash::HashMap, Context};
+use serde::Deserialize;
+use std::sync::{Arc, Mutex};
+
+#[derive(Deserialize)]
+pub struct Session {
+    pub id: i64,
+    /// unix time in seconds
+    pub started: Option<u64>,
+    pub ended: Option<u64>,
+    pub keys: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Dump {
+    values: HashMap<String, Vec<Option<f32>>>,
+}
+
+#[derive(Default)]
+pub struct Sessions {
+    socket: Option<url::Url>,
+    list: Arc<Mutex<Vec<Session>>>,
+    selected: Option<i64>,
+    /// samples of the selected session once downloaded
+    values: Arc<Mutex<Option<Values>>>,
+}
+
+impl Sessions {
+    pub fn new(socket: &url::Url) -> Self {
+        Self {
+            socket: Some(socket.clone()),
+            ..Default::default()
+        }
+    }
+
+    pub fn refresh(&self, ctx: &Context) {
+        let Some(socket) = &self.socket else {
+            return;
+        };
+        let list = self.list.clone();
+        request::get_json(
+            request::route(socket, &["sessions"]),
+            ctx,
+            move |r| match r {
+                Ok(sessions) => *list.lock().unwrap() = sessions,
+                Err(e) => log::error!("failed to fetch sessions: {}", e),
+            },
+        );
+    }
+
+    /// Past session shown instead of the live one
+    pub fn selected(&self) -> Option<i64> {
+        self.selected
+    }
+
+    pub fn select(&mut self, ctx: &Context, session: Option<i64>) {
+        self.selected = session;
+        *self.values.lock().unwrap() = None;
+        let (Some(socket), Some(session)) = (&self.socket, session) else {
+            return;
+        };
+        let mut url = request::route(socket, &["download.json"]);
+        url.query_pairs_mut()
+            .append_pair("session", &session.to_string());
+        let values = self.values.clone();
+        request::get_json(url, ctx, move |r: Result<Dump, String>| match r {
+            Ok(dump) => {
+                let len = dump
+                    .values
+                    .values()
+                    .map(|v| v.len())
+                    .max()
+                    .unwrap_or_default();
+                let mut past = Values::with_capacity(len);
+                for (k, v) in dump.values {
+                    past.push(k, v.into_iter().map(|v| v.unwrap_or(f32::NAN)).collect());
+                }
+                *values.lock().unwrap() = Some(past);
+            }
+            Err(e) => log::error!("failed to download session {}: {}", session, e),
+        });
+    }
+
+    /// Downloaded samples of the selected session
+    pub fn values(&self) -> std::sync::MutexGuard<'_, Option<Values>> {
+        self.values.lock().unwrap()
+    }
+
+    /// Combo box choosing between the live and the past sessions, true on change
+    pub fn ui(&mut self, ui: &mut egui::Ui, current: i64) -> bool {
+        let mut selected = self.selected;
+        let label = |id: Option<i64>| match id {
+            None => format!("live ({})", current),
+            Some(id) => format!("session {}", id),
+        };
+        let response = egui::ComboBox::from_id_source("session")
+            .selected_text(label(selected))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut selected, None, label(None));
+                for s in self.list.lock().unwrap().iter().rev() {
+                    if s.id == current {
+                        continue;
+                    }
+                    let duration = s
+                        .ended
+                        .unwrap_or_default()
+                        .saturating_sub(s.started.unwrap_or_default());
+                    let text = format!(
+                        "{}: {}s, {}",
+                        label(Some(s.id)),
+                        duration,
+                        s.keys.join(", ")
+                    );
+                    ui.selectable_value(&mut selected, Some(s.id), text);
+                }
+            });
+        if response.response.clicked() {
+            self.refresh(ui.ctx());
+        }
+        if selected == self.selected {
+            return false;
+        }
+        self.select(ui.ctx(), selected);
+        true
+    }
+}
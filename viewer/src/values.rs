@@ -70,9 +70,10 @@ impl Values {
             .entry(key)
             .or_insert_with(|| VecDeque::with_capacity(self.max_len));
         if v.len() + values.len() > self.max_len {
-            v.drain(0..(v.len() + values.len() - self.max_len));
+            v.drain(0..(v.len() + values.len() - self.max_len).min(v.len()));
         }
-        v.extend(values);
+        let skip = values.len().saturating_sub(self.max_len);
+        v.extend(&values[skip..]);
     }
 
     pub fn contains_key(&self, key: &str) -> bool {